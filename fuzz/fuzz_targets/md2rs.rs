@@ -0,0 +1,19 @@
+#![no_main]
+// Checks that tango converts any Markdown to Rust without panicking,
+// and that whatever it warns of is on a line of the input.
+//
+//     cargo +nightly fuzz run md2rs
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    if let Ok(rs) = tango::md_to_rs(text) {
+        for warning in &rs.warnings {
+            assert!(1 <= warning.line() && warning.line() <= text.lines().count(), "{}", warning);
+        }
+    }
+});
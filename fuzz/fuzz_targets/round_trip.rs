@@ -0,0 +1,32 @@
+#![no_main]
+// Checks that whatever tango brings into normal form, Markdown or
+// Rust, converts back unchanged (see `tango::normalize_md`).
+//
+//     cargo +nightly fuzz run round_trip
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+
+    // As Markdown; only without warnings is the round trip exact.
+    if let Ok(ref rs) = tango::md_to_rs(text) {
+        if rs.warnings.is_empty() {
+            let normal = tango::rs_to_md(&rs.output).unwrap().output;
+            let normal_rs = tango::md_to_rs(&normal).unwrap();
+            assert_eq!(normal_rs.output, rs.output, "normalizing changed the code");
+            assert_eq!(tango::rs_to_md(&normal_rs.output).unwrap().output, normal);
+        }
+    }
+
+    // As Rust.
+    if let Ok(ref normal) = tango::normalize_rs(text) {
+        if normal.warnings.is_empty() {
+            let md = tango::rs_to_md(&normal.output).unwrap().output;
+            assert_eq!(tango::md_to_rs(&md).unwrap().output, normal.output);
+        }
+    }
+});
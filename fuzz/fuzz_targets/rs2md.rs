@@ -0,0 +1,17 @@
+#![no_main]
+// Checks that tango converts any Rust to Markdown without panicking,
+// and that whatever it warns of is on a line of the input.
+//
+//     cargo +nightly fuzz run rs2md
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    for warning in &tango::rs_to_md(text).unwrap().warnings {
+        assert!(1 <= warning.line() && warning.line() <= text.lines().count(), "{}", warning);
+    }
+});
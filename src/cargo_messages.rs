@@ -0,0 +1,102 @@
+// Pointing the diagnostics in cargo's JSON messages at the `.md`
+// files that `.rs` files were generated from.
+//
+// `cargo build --message-format=json` writes one JSON object per
+// line. A compiler message carries a diagnostic whose spans (and
+// those of its children, and of the macro invocations they were
+// expanded from) each name a file, with a range of lines and columns:
+//
+//     {"reason":"compiler-message", ..., "message":{..., "spans":[
+//         {"file_name":"src/lib.rs","line_start":8,"line_end":8,
+//          "column_start":5,"column_end":22, ...}], "children":[...]}}
+//
+// A span whose start and end map to the same `.md` file is rewritten
+// to point there. Every other span, like every line that is not a
+// compiler message (or not JSON at all), is passed through unchanged.
+
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{self, Value};
+
+/// Maps a line and column of a `.rs` file to the `.md` file, line
+/// and column they came from, if they came from one.
+pub type Lookup<'a> = dyn FnMut(&Path, usize, usize) -> Option<(PathBuf, usize, usize)> + 'a;
+
+pub fn rewrite<R: BufRead, W: Write>(r: R, mut w: W, lookup: &mut Lookup) -> io::Result<()> {
+    for line in r.lines() {
+        let line = (line)?;
+        // Lines left alone are copied as they were, rather than
+        // re-serialized.
+        let rewritten = match serde_json::from_str::<Value>(&line) {
+            Ok(mut message) => {
+                if rewrite_message(&mut message, lookup) { Some(message.to_string()) } else { None }
+            }
+            Err(_) => None,
+        };
+        (writeln!(w, "{}", rewritten.as_ref().unwrap_or(&line)))?;
+    }
+    w.flush()
+}
+
+// Rewrites the spans of a cargo message (or of a bare diagnostic, as
+// from `rustc --error-format=json`); returns whether any changed.
+fn rewrite_message(message: &mut Value, lookup: &mut Lookup) -> bool {
+    if message.get("reason").and_then(Value::as_str) == Some("compiler-message") {
+        match message.get_mut("message") {
+            Some(diagnostic) => rewrite_diagnostic(diagnostic, lookup),
+            None => false,
+        }
+    } else if message.get("spans").is_some() {
+        rewrite_diagnostic(message, lookup)
+    } else {
+        false
+    }
+}
+
+fn rewrite_diagnostic(diagnostic: &mut Value, lookup: &mut Lookup) -> bool {
+    let mut changed = false;
+    if let Some(spans) = diagnostic.get_mut("spans").and_then(Value::as_array_mut) {
+        for span in spans {
+            changed |= rewrite_span(span, lookup);
+        }
+    }
+    if let Some(children) = diagnostic.get_mut("children").and_then(Value::as_array_mut) {
+        for child in children {
+            changed |= rewrite_diagnostic(child, lookup);
+        }
+    }
+    changed
+}
+
+fn rewrite_span(span: &mut Value, lookup: &mut Lookup) -> bool {
+    let mut changed = false;
+    if let Some(expansion) = span.get_mut("expansion").filter(|e| e.is_object()) {
+        for key in &["span", "def_site_span"] {
+            if let Some(inner) = expansion.get_mut(*key) {
+                changed |= rewrite_span(inner, lookup);
+            }
+        }
+    }
+    let field = |span: &Value, key: &str| span.get(key).and_then(Value::as_u64).map(|n| n as usize);
+    let (file, line_start, line_end, column_start, column_end) =
+        match (span.get("file_name").and_then(Value::as_str),
+               field(span, "line_start"), field(span, "line_end"),
+               field(span, "column_start"), field(span, "column_end")) {
+            (Some(file), Some(a), Some(b), Some(c), Some(d)) => (PathBuf::from(file), a, b, c, d),
+            _ => return changed,
+        };
+    let (start, end) = match (lookup(&file, line_start, column_start), lookup(&file, line_end, column_end)) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return changed,
+    };
+    if start.0 != end.0 {
+        return changed;
+    }
+    span["file_name"] = Value::from(start.0.display().to_string());
+    span["line_start"] = Value::from(start.1);
+    span["column_start"] = Value::from(start.2);
+    span["line_end"] = Value::from(end.1);
+    span["column_end"] = Value::from(end.2);
+    true
+}
@@ -0,0 +1,33 @@
+// A `.rs` file assembled from a sequence of `.md` chapters.
+//
+// A manifest in the configuration lists the chapters of a `.rs` file,
+// in order:
+//
+//     [files.chapters]
+//     "src/lib.rs" = ["lit/01-intro.md", "lit/02-types.md"]
+//
+// The `.rs` file then holds each chapter's conversion in turn, each
+// starting with a marker line naming the chapter:
+//
+//     //@chapter lit/01-intro.md
+//     //@ # Introduction
+//     ...
+//     //@chapter lit/02-types.md
+//     //@ # Types
+//     ...
+//
+// Going back, the `.rs` file is split at the markers, and each part
+// converted into the chapter it names. The chapters are synced
+// together, as a group, with the `.rs` file.
+
+const CHAPTER_START: &str = "//@chapter ";
+
+/// The marker line that starts the chapter `name`.
+pub fn start(name: &str) -> String {
+    format!("{}{}", CHAPTER_START, name)
+}
+
+/// If `line` starts a chapter, returns the chapter's name.
+pub fn name(line: &str) -> Option<&str> {
+    line.strip_prefix(CHAPTER_START).map(|name| name.trim_end())
+}
@@ -0,0 +1,245 @@
+// Named code chunks, as in noweb.
+//
+// A Rust block whose info string ends in `<<name>>=` defines the chunk
+// `name`, and a code line holding just `<<name>>` (possibly indented)
+// refers to it. In the `.rs` file, each reference is replaced by the
+// chunk's code, indented like the reference and bracketed by marker
+// lines:
+//
+//     fn main() {
+//         //@<< parse header
+//         let header = parse_header(&input);
+//         //@>>
+//     }
+//
+// while the definition site keeps only its `//@@ <<parse header>>=`
+// meta note. Going back, rs2md takes the chunk's code from (the first
+// of) its expansions. Chunks that are never referenced keep their code
+// at the definition site, as for ordinary blocks.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as ErrorTrait;
+use std::fmt;
+use std::mem;
+
+use super::{Diagnostic, Severity, Warning};
+
+const EXPANSION_START: &str = "//@<< ";
+const EXPANSION_END: &str = "//@>>";
+
+/// A misuse of chunks, at a (1-based) line and column of the `.md`
+/// file (or of the chapter it is in).
+#[derive(Debug)]
+pub enum ChunkError {
+    /// `<<name>>` at the given line of the `.md` file names no chunk.
+    Undefined { name: String, line: usize, column: usize },
+    /// The chunk defined at the given line is defined earlier, too.
+    Duplicate { name: String, line: usize, column: usize },
+    /// The chunks, starting with the one defined at the given line,
+    /// refer to one another in a cycle.
+    Cyclic { names: Vec<String>, line: usize, column: usize },
+}
+
+impl ChunkError {
+    /// A stable, machine-readable name for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ChunkError::Undefined { .. } => "undefined-chunk",
+            ChunkError::Duplicate { .. } => "duplicate-chunk",
+            ChunkError::Cyclic { .. } => "cyclic-chunks",
+        }
+    }
+
+    /// The error as a diagnostic, in no file as yet.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let (message, line, column) = match *self {
+            ChunkError::Undefined { ref name, line, column } =>
+                (format!("reference to undefined chunk `<<{}>>`", name), line, column),
+            ChunkError::Duplicate { ref name, line, column } =>
+                (format!("chunk `<<{}>>` is already defined", name), line, column),
+            ChunkError::Cyclic { ref names, line, column } => {
+                let mut message = "chunks refer to each other in a cycle: ".to_string();
+                for name in names {
+                    message.push_str(&format!("<<{}>> -> ", name));
+                }
+                message.push_str(&format!("<<{}>>", names[0]));
+                (message, line, column)
+            }
+        };
+        Diagnostic::new(Severity::Error, self.code(), message, line, column)
+    }
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}", self.diagnostic())
+    }
+}
+
+impl ErrorTrait for ChunkError {}
+
+/// If `line` refers to a chunk, returns its indentation and the name.
+pub fn reference(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start();
+    let indent = &line[..line.len() - rest.len()];
+    let rest = rest.trim_end();
+    if rest.len() > 4 && rest.starts_with("<<") && rest.ends_with(">>") {
+        let name = &rest[2..rest.len() - 2];
+        if !name.contains('<') && !name.contains('>') {
+            return Some((indent, name));
+        }
+    }
+    None
+}
+
+pub fn expansion_start(indent: &str, name: &str) -> String {
+    format!("{}{}{}", indent, EXPANSION_START, name)
+}
+
+pub fn expansion_end(indent: &str) -> String {
+    format!("{}{}", indent, EXPANSION_END)
+}
+
+/// The chunks defined in a document: for each name, the (1-based)
+/// line of the definition and the code lines of its block.
+pub type Definitions = BTreeMap<String, (usize, Vec<String>)>;
+
+/// Fails if some chunk refers to itself, directly or otherwise, with
+/// the names of the chunks in the cycle.
+pub fn check_cycles(defs: &Definitions) -> Result<(), Vec<String>> {
+    let mut done = BTreeSet::new();
+    for name in defs.keys() {
+        let mut path = Vec::new();
+        (visit(defs, name, &mut path, &mut done))?;
+    }
+    Ok(())
+}
+
+fn visit<'a>(defs: &'a Definitions, name: &'a str,
+             path: &mut Vec<&'a str>, done: &mut BTreeSet<&'a str>) -> Result<(), Vec<String>> {
+    if done.contains(name) {
+        return Ok(());
+    }
+    if let Some(i) = path.iter().position(|n| *n == name) {
+        return Err(path[i..].iter().map(|n| n.to_string()).collect());
+    }
+    path.push(name);
+    for line in &defs[name].1 {
+        if let Some((_, r)) = reference(line) {
+            if defs.contains_key(r) {
+                (visit(defs, r, path, done))?;
+            }
+        }
+    }
+    path.pop();
+    done.insert(name);
+    Ok(())
+}
+
+// An expansion being read back, the line it starts on, and the
+// indentation of its markers.
+#[derive(Debug)]
+struct Frame {
+    name: String,
+    line: usize,
+    indent: String,
+    body: Vec<String>,
+    raw: Vec<(usize, String)>,
+}
+
+/// Replaces each expansion in the `.rs` lines `lines` with the
+/// reference it came from. Returns the remaining lines, and the code
+/// of each chunk expanded (with its own references restored).
+pub fn untangle(lines: Vec<String>) -> (Vec<String>, BTreeMap<String, Vec<String>>) {
+    let mut untangler = Untangler::new();
+    let mut out = Vec::new();
+    for line in lines {
+        untangler.feed(line, &mut out);
+    }
+    untangler.finish(&mut out);
+    (out.into_iter().map(|(_, line)| line).collect(), untangler.take_chunks())
+}
+
+/// Untangles `.rs` lines as they come; see `untangle`.
+#[derive(Default, Debug)]
+pub struct Untangler {
+    // The number of lines fed so far.
+    line: usize,
+    stack: Vec<Frame>,
+    chunks: BTreeMap<String, Vec<String>>,
+    warnings: Vec<Warning>,
+}
+
+impl Untangler {
+    pub fn new() -> Untangler {
+        Untangler::default()
+    }
+
+    /// Takes the next line, adding to `out` the lines that are done
+    /// with, each with its line number: all but those within an
+    /// expansion, which are held back until the expansion ends, to be
+    /// replaced by a reference numbered as the expansion's first line.
+    pub fn feed(&mut self, line: String, out: &mut Vec<(usize, String)>) {
+        self.line += 1;
+        let rest = line.trim_start();
+        let indent = line[..line.len() - rest.len()].to_string();
+        if let Some(name) = rest.strip_prefix(EXPANSION_START) {
+            let name = name.trim().to_string();
+            let raw = vec![(self.line, line.clone())];
+            self.stack.push(Frame { name, line: self.line, indent, body: Vec::new(), raw });
+        } else if rest == EXPANSION_END && !self.stack.is_empty() {
+            let frame = self.stack.pop().unwrap();
+            let outer = self.stack.last().map_or("", |f| &f.indent[..]).to_string();
+            let relative = frame.indent.strip_prefix(&outer[..]).unwrap_or(&frame.indent);
+            let reference = format!("{}<<{}>>", relative, frame.name);
+            self.chunks.entry(frame.name).or_insert(frame.body);
+            match self.stack.last_mut() {
+                Some(f) => {
+                    f.raw.extend(frame.raw);
+                    f.raw.push((self.line, line.clone()));
+                    f.body.push(reference);
+                }
+                None => out.push((frame.line, reference)),
+            }
+        } else if let Some(f) = self.stack.last_mut() {
+            // Code holds no Markdown, nor meta notes; but for the
+            // markers of hidden lines.
+            if rest.starts_with("//@") && rest != "//@#" {
+                self.warnings.push(Warning::CommentInExpansion {
+                    comment: rest.to_string(),
+                    line: self.line,
+                    column: indent.chars().count() + 1,
+                });
+            } else {
+                let body = if line.starts_with(&f.indent[..]) { &line[f.indent.len()..] } else { rest };
+                f.body.push(body.to_string());
+            }
+            f.raw.push((self.line, line.clone()));
+        } else {
+            out.push((self.line, line));
+        }
+    }
+
+    /// Adds to `out` the lines held back at the end of the input.
+    pub fn finish(&mut self, out: &mut Vec<(usize, String)>) {
+        // An expansion left open is not one; keep its lines as they are.
+        for frame in self.stack.drain(..) {
+            self.warnings.push(Warning::UnclosedExpansion {
+                name: frame.name,
+                line: frame.line,
+                column: frame.indent.chars().count() + 1,
+            });
+            out.extend(frame.raw);
+        }
+    }
+
+    /// Takes the code of the chunks expanded so far.
+    pub fn take_chunks(&mut self) -> BTreeMap<String, Vec<String>> {
+        mem::take(&mut self.chunks)
+    }
+
+    /// Takes the warnings about the expansions read so far.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+}
@@ -0,0 +1,171 @@
+// Reading `Config` settings from TOML, either from a `tango.toml`:
+//
+//     src-dir = "src"
+//     lit-dir = "lit"
+//     emit-rerun-if = true
+//     sync = "content-hash"          # or "mtime"
+//     fence-languages = ["rust", "rs"]
+//
+//     [files]
+//     include = ["src/**/*.rs"]
+//     exclude = ["src/generated/**"]
+//
+//     [files.map]
+//     "src/lib.rs" = "README.md"
+//
+//     [files.chapters]
+//     "src/parser.rs" = ["lit/01-intro.md", "lit/02-grammar.md"]
+//
+//     [playground]
+//     url = "https://play.rust-lang.org/"
+//     channel = "nightly"
+//     check-urls = true
+//
+// or from the same keys under `[package.metadata.tango]` in a
+// `Cargo.toml`.
+
+use std::path::Path;
+
+use toml::Value;
+use toml::value::Table;
+
+use super::{Config, Error, Result, SyncMode};
+
+fn error<X>(msg: String) -> Result<X> {
+    Err(Error::ConfigError(msg))
+}
+
+// Prefixes a configuration error with the file it came from.
+pub fn in_file(path: &Path, e: Error) -> Error {
+    match e {
+        Error::ConfigError(msg) => Error::ConfigError(format!("{}: {}", path.display(), msg)),
+        e => e,
+    }
+}
+
+fn parse(text: &str) -> Result<Value> {
+    text.parse().or_else(|e: ::toml::de::Error| error(e.to_string()))
+}
+
+fn table<'a>(v: &'a Value, key: &str) -> Result<&'a Table> {
+    match v.as_table() {
+        Some(t) => Ok(t),
+        None => error(format!("`{}` must be a table", key)),
+    }
+}
+
+fn string<'a>(v: &'a Value, key: &str) -> Result<&'a str> {
+    match v.as_str() {
+        Some(s) => Ok(s),
+        None => error(format!("`{}` must be a string", key)),
+    }
+}
+
+fn boolean(v: &Value, key: &str) -> Result<bool> {
+    match v.as_bool() {
+        Some(b) => Ok(b),
+        None => error(format!("`{}` must be a boolean", key)),
+    }
+}
+
+fn strings<'a>(v: &'a Value, key: &str) -> Result<Vec<&'a str>> {
+    match v.as_array() {
+        Some(items) => items.iter().map(|item| match item.as_str() {
+            Some(s) => Ok(s),
+            None => error(format!("`{}` must contain only strings", key)),
+        }).collect(),
+        None => error(format!("`{}` must be an array of strings", key)),
+    }
+}
+
+/// Applies the settings of a `tango.toml`.
+pub fn apply(config: &mut Config, text: &str) -> Result<()> {
+    let value = (parse(text))?;
+    apply_table((table(&value, ""))?, config, "")
+}
+
+/// Applies the settings under `[package.metadata.tango]`, if any, of
+/// a `Cargo.toml`.
+pub fn apply_cargo_metadata(config: &mut Config, text: &str) -> Result<()> {
+    let value = (parse(text))?;
+    let tango = value.get("package")
+        .and_then(|p| p.get("metadata"))
+        .and_then(|m| m.get("tango"));
+    match tango {
+        Some(tango) => apply_table((table(tango, "package.metadata.tango"))?,
+                                   config, "package.metadata.tango."),
+        None => Ok(()),
+    }
+}
+
+fn apply_table(t: &Table, config: &mut Config, prefix: &str) -> Result<()> {
+    for (key, v) in t {
+        let name = format!("{}{}", prefix, key);
+        match &key[..] {
+            "src-dir" => { config.set_src_dir((string(v, &name))?.to_string()); }
+            "lit-dir" => { config.set_lit_dir((string(v, &name))?.to_string()); }
+            "emit-rerun-if" => { config.set_emit_rerun_if((boolean(v, &name))?); }
+            "sync" => {
+                let mode = match (string(v, &name))? {
+                    "content-hash" => SyncMode::ContentHash,
+                    "mtime" => SyncMode::Mtime,
+                    other => return error(format!(
+                        "`{}` must be \"content-hash\" or \"mtime\", not {:?}", name, other)),
+                };
+                config.set_sync_mode(mode);
+            }
+            "fence-languages" => {
+                let languages = (strings(v, &name))?;
+                if languages.is_empty() {
+                    return error(format!("`{}` must name at least one language", name));
+                }
+                config.set_fence_languages(languages.iter().map(|l| l.to_string()).collect());
+            }
+            "files" => (apply_files((table(v, &name))?, config, &name))?,
+            "playground" => (apply_playground((table(v, &name))?, config, &name))?,
+            _ => return error(format!("unknown setting `{}`", name)),
+        }
+    }
+    Ok(())
+}
+
+fn apply_files(t: &Table, config: &mut Config, prefix: &str) -> Result<()> {
+    for (key, v) in t {
+        let name = format!("{}.{}", prefix, key);
+        match &key[..] {
+            "include" => for pattern in (strings(v, &name))? {
+                (config.include(pattern))?;
+            },
+            "exclude" => for pattern in (strings(v, &name))? {
+                (config.exclude(pattern))?;
+            },
+            "map" => for (rs, md) in (table(v, &name))? {
+                let md = (string(md, &format!("{}.{:?}", name, rs)))?;
+                config.map_file(rs.as_str(), md);
+            },
+            "chapters" => for (rs, mds) in (table(v, &name))? {
+                let key = format!("{}.{:?}", name, rs);
+                let mds = (strings(mds, &key))?;
+                if mds.is_empty() {
+                    return error(format!("`{}` must name at least one chapter", key));
+                }
+                config.map_chapters(rs.as_str(), mds);
+            },
+            _ => return error(format!("unknown setting `{}`", name)),
+        }
+    }
+    Ok(())
+}
+
+fn apply_playground(t: &Table, config: &mut Config, prefix: &str) -> Result<()> {
+    for (key, v) in t {
+        let name = format!("{}.{}", prefix, key);
+        match &key[..] {
+            "url" => { config.set_playground_url((string(v, &name))?.to_string()); }
+            "channel" => { config.set_playground_channel((string(v, &name))?.to_string()); }
+            "check-urls" => { config.check_playground_urls((boolean(v, &name))?); }
+            _ => return error(format!("unknown setting `{}`", name)),
+        }
+    }
+    Ok(())
+}
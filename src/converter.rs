@@ -0,0 +1,58 @@
+use std::io::{BufRead, Write};
+
+use super::{Result, Warning};
+
+/// One direction of the conversion between a `.md` file and its `.rs`
+/// twin, fed the lines of its input one at a time; see `MdToRs` and
+/// `RsToMd`.
+///
+/// A converter converts a single document: once `finish`ed, it is not
+/// to be fed any more lines.
+///
+/// Feeding lines one at a time does not by itself bound the memory a
+/// conversion takes: `MdToRs` holds on to the rest of a document from
+/// its first chunk on, as where a chunk's code goes depends on what
+/// follows.
+pub trait Converter {
+    /// Takes the next line of the input (without its line ending),
+    /// writing to `w` whatever output it completes. How much that is
+    /// depends on the direction: rs2md writes most lines out as it
+    /// goes, while md2rs writes out the lines up to each fenced block
+    /// once the block closes, since which of its lines are Rust code
+    /// can depend on what follows them.
+    fn feed_line(&mut self, line: &str, w: &mut dyn Write) -> Result<()>;
+
+    /// Writes the rest of the output to `w`, once every line of the
+    /// input has been fed.
+    fn finish(&mut self, w: &mut dyn Write) -> Result<()>;
+
+    /// Takes the warnings about the input found so far. Unlike errors,
+    /// these do not stop the conversion.
+    fn take_warnings(&mut self) -> Vec<Warning>;
+
+    /// Converts the whole of `text`.
+    fn convert_str(&mut self, text: &str, w: &mut dyn Write) -> Result<()> {
+        for line in text.lines() {
+            (self.feed_line(line, w))?;
+        }
+        self.finish(w)
+    }
+
+    /// Converts the lines read from `r`.
+    fn convert_reader<R: BufRead>(&mut self, r: R, w: &mut dyn Write) -> Result<()> where Self: Sized {
+        for line in r.lines() {
+            (self.feed_line(&(line)?, w))?;
+        }
+        self.finish(w)
+    }
+
+    /// Converts a sequence of lines, each without its line ending.
+    fn convert_lines<I>(&mut self, lines: I, w: &mut dyn Write) -> Result<()>
+        where I: IntoIterator, I::Item: AsRef<str>, Self: Sized
+    {
+        for line in lines {
+            (self.feed_line(line.as_ref(), w))?;
+        }
+        self.finish(w)
+    }
+}
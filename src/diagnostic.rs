@@ -0,0 +1,99 @@
+// Diagnostics: the warnings and errors of a conversion, each placed
+// at a line and column of the file it is about, and rendered as rustc
+// renders its own:
+//
+//     warning[unclosed-fence]: code block is never closed
+//      --> src/lib.md:12:1
+//        |
+//     12 | ```rust
+//        | ^^^^^^^
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Severity {
+    /// The conversion cannot go on.
+    Error,
+    /// The conversion goes on, but its output may not be what was
+    /// meant.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Error => write!(w, "error"),
+            Severity::Warning => write!(w, "warning"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A stable, machine-readable name for the kind of problem, such
+    /// as `unclosed-fence`.
+    pub code: &'static str,
+    pub message: String,
+    /// The file the problem is in, if it came from one.
+    pub path: Option<PathBuf>,
+    /// The (1-based) line and column of the problem; the column counts
+    /// characters.
+    pub line: usize,
+    pub column: usize,
+    /// The text of the offending line, if known.
+    pub snippet: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: String, line: usize, column: usize) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code,
+            message,
+            path: None,
+            line,
+            column,
+            snippet: None,
+        }
+    }
+
+    /// Places the diagnostic in the file at `path`, whose content is
+    /// `text`, taking the offending line from it.
+    pub fn in_file(mut self, path: &Path, text: &str) -> Diagnostic {
+        self.path = Some(path.to_path_buf());
+        self.snippet = text.lines().nth(self.line.wrapping_sub(1))
+            .map(|line| line.trim_end_matches('\r').to_string());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        (writeln!(w, "{}[{}]: {}", self.severity, self.code, self.message))?;
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        match self.path {
+            Some(ref path) => (write!(w, "{}--> {}:{}:{}", gutter, path.display(), self.line, self.column))?,
+            None => (write!(w, "{}--> {}:{}", gutter, self.line, self.column))?,
+        }
+        if let Some(ref snippet) = self.snippet {
+            // Tabs are shown as spaces, so that the carets line up.
+            let shown = snippet.replace('\t', "    ");
+            let before: String = snippet.chars().take(self.column.saturating_sub(1)).collect();
+            let start = before.replace('\t', "    ").chars().count();
+            let end = shown.trim_end().chars().count().max(start + 1);
+            (write!(w, "\n{} |", gutter))?;
+            (write!(w, "\n{} | {}", number, shown))?;
+            (write!(w, "\n{} | {}{}", gutter, " ".repeat(start), "^".repeat(end - start)))?;
+        }
+        Ok(())
+    }
+}
+
+/// The (1-based) column, in characters, of the byte offset `at` of
+/// `line`.
+pub fn column(line: &str, at: usize) -> usize {
+    line[..at].chars().count() + 1
+}
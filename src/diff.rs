@@ -0,0 +1,172 @@
+// Line-based differencing, shared by the three-way merge and by the
+// unified diffs that `check` reports.
+
+// Returns the index pairs of a longest common subsequence of `a` and
+// `b`, in increasing order.
+pub fn common_lines(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    common_lines_into(a, b, (0, 0), &mut pairs);
+    pairs
+}
+
+// Myers' O(ND) difference algorithm in its linear-space form: the
+// middle snake of an optimal edit path splits the problem in two,
+// which are solved in turn, so that no frontiers need be kept. The
+// pairs found are offset by `at` and appended to `pairs`.
+fn common_lines_into(a: &[&str], b: &[&str], at: (usize, usize), pairs: &mut Vec<(usize, usize)>) {
+    let mut prefix = 0;
+    while prefix < a.len() && prefix < b.len() && a[prefix] == b[prefix] {
+        prefix += 1;
+    }
+    let mut suffix = 0;
+    while suffix < a.len() - prefix && suffix < b.len() - prefix &&
+        a[a.len() - 1 - suffix] == b[b.len() - 1 - suffix]
+    {
+        suffix += 1;
+    }
+    pairs.extend((0..prefix).map(|k| (at.0 + k, at.1 + k)));
+
+    let (mid_a, mid_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    if !mid_a.is_empty() && !mid_b.is_empty() {
+        let at_mid = (at.0 + prefix, at.1 + prefix);
+        let (x, y, u, v) = middle_snake(mid_a, mid_b);
+        common_lines_into(&mid_a[..x], &mid_b[..y], at_mid, pairs);
+        pairs.extend((0..u - x).map(|k| (at_mid.0 + x + k, at_mid.1 + y + k)));
+        common_lines_into(&mid_a[u..], &mid_b[v..], (at_mid.0 + u, at_mid.1 + v), pairs);
+    }
+    pairs.extend((0..suffix).map(|k| (at.0 + a.len() - suffix + k, at.1 + b.len() - suffix + k)));
+}
+
+// Finds the middle snake of an optimal edit path from `a` to `b` (both
+// non-empty), searching forwards from the start and backwards from
+// the end at once until the two meet. Returns the snake as `(x, y, u,
+// v)`: `a[x..u]` is the same as `b[y..v]`.
+fn middle_snake(a: &[&str], b: &[&str]) -> (usize, usize, usize, usize) {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    // The furthest `x` reached on each diagonal, forwards, and
+    // backwards (counting from the ends of `a` and `b`).
+    let mut forward = vec![0isize; (2 * max + 3) as usize];
+    let mut backward = vec![0isize; (2 * max + 3) as usize];
+
+    for d in 0..(max + 1) {
+        let mut k = -d;
+        while k <= d {
+            let (start, end) = extend(&mut forward, k, d, offset, |x, y| a[x] == b[y], n, m);
+            // Diagonal `k` is diagonal `delta - k` of the backward
+            // search, which has taken `d - 1` steps.
+            let back_k = delta - k;
+            if odd && back_k.abs() < d && end.0 + backward[(back_k + offset) as usize] >= n {
+                return (start.0 as usize, start.1 as usize, end.0 as usize, end.1 as usize);
+            }
+            k += 2;
+        }
+        let mut k = -d;
+        while k <= d {
+            let (start, end) = extend(&mut backward, k, d, offset,
+                                      |x, y| a[a.len() - 1 - x] == b[b.len() - 1 - y], n, m);
+            let forward_k = delta - k;
+            if !odd && forward_k.abs() <= d && end.0 + forward[(forward_k + offset) as usize] >= n {
+                return ((n - end.0) as usize, (m - end.1) as usize,
+                        (n - start.0) as usize, (m - start.1) as usize);
+            }
+            k += 2;
+        }
+    }
+    unreachable!("the searches meet within (N + M) / 2 steps")
+}
+
+// Takes step `d` of a search on diagonal `k`, recording the furthest
+// point reached in `v`. Returns the points the diagonal's snake runs
+// between.
+fn extend<F>(v: &mut [isize], k: isize, d: isize, offset: isize, same: F, n: isize, m: isize)
+             -> ((isize, isize), (isize, isize))
+    where F: Fn(usize, usize) -> bool
+{
+    let idx = (k + offset) as usize;
+    let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+        v[idx + 1]
+    } else {
+        v[idx - 1] + 1
+    };
+    let mut y = x - k;
+    let start = (x, y);
+    while x < n && y < m && same(x as usize, y as usize) {
+        x += 1;
+        y += 1;
+    }
+    v[idx] = x;
+    (start, (x, y))
+}
+
+#[derive(PartialEq)]
+enum Op { Equal, Delete, Insert }
+
+/// Renders the differences between `a` and `b` in unified diff
+/// format, with three lines of context; returns the empty string if
+/// they are the same.
+pub fn unified(a_name: &str, a: &str, b_name: &str, b: &str) -> String {
+    const CONTEXT: usize = 3;
+    let a: Vec<&str> = a.lines().collect();
+    let b: Vec<&str> = b.lines().collect();
+
+    // Flatten the common subsequence into an edit script.
+    let mut ops: Vec<(Op, &str)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (mi, mj) in common_lines(&a, &b).into_iter().chain(Some((a.len(), b.len()))) {
+        ops.extend(a[i..mi].iter().map(|l| (Op::Delete, *l)));
+        ops.extend(b[j..mj].iter().map(|l| (Op::Insert, *l)));
+        if mi < a.len() {
+            ops.push((Op::Equal, a[mi]));
+        }
+        i = mi + 1;
+        j = mj + 1;
+    }
+
+    let changes: Vec<usize> = ops.iter().enumerate()
+        .filter(|&(_, op)| op.0 != Op::Equal)
+        .map(|(k, _)| k)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", a_name, b_name);
+    let mut c = 0;
+    while c < changes.len() {
+        // Extend the hunk while the next change is close enough that
+        // the contexts would touch.
+        let mut last = c;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= 2 * CONTEXT {
+            last += 1;
+        }
+        let start = changes[c].saturating_sub(CONTEXT);
+        let end = ::std::cmp::min(ops.len(), changes[last] + CONTEXT + 1);
+
+        let (mut a_pos, mut b_pos) = (0, 0);
+        for op in &ops[..start] {
+            match op.0 {
+                Op::Equal => { a_pos += 1; b_pos += 1; }
+                Op::Delete => a_pos += 1,
+                Op::Insert => b_pos += 1,
+            }
+        }
+        let a_count = ops[start..end].iter().filter(|op| op.0 != Op::Insert).count();
+        let b_count = ops[start..end].iter().filter(|op| op.0 != Op::Delete).count();
+        let a_start = if a_count == 0 { a_pos } else { a_pos + 1 };
+        let b_start = if b_count == 0 { b_pos } else { b_pos + 1 };
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", a_start, a_count, b_start, b_count));
+        for &(ref op, line) in &ops[start..end] {
+            let prefix = match *op { Op::Equal => ' ', Op::Delete => '-', Op::Insert => '+' };
+            out.push(prefix);
+            out.push_str(line);
+            out.push('\n');
+        }
+        c = last + 1;
+    }
+    out
+}
@@ -0,0 +1,129 @@
+// The info string of a fenced Rust code block, such as rustdoc's
+// `rust,ignore` or pandoc's `{.rust .numberLines}`.
+//
+// In the `.rs` file, whatever follows the language is carried on a
+// `//@@` line (the "meta note") ahead of the code. A pandoc-style
+// note is written as ` {` followed by the attributes after `{.rust`,
+// so `{.rust .numberLines}` becomes `//@@ { .numberLines}`; any other
+// note is the rest of the info string verbatim, so `rust,ignore`
+// becomes `//@@,ignore`. A rest that would read as something else
+// (`rust {.numberLines}` would pass for pandoc, `rust@x` for a `//@@@`
+// line) is escaped with a leading `\`, as is one that starts with
+// `\` itself: `//@@\ {.numberLines}`.
+
+// Attributes that mark a block as not expected to compile. The code
+// of such blocks is commented out in the `.rs` file.
+const NOT_COMPILED: &[&str] = &["ignore", "compile_fail"];
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Info {
+    pandoc: bool,
+    // Everything after the language (for pandoc, after `{.rust`).
+    rest: String,
+}
+
+impl Info {
+    /// Parses `info` if it marks a code block in one of `languages`.
+    pub fn parse(info: &str, languages: &[String]) -> Option<Info> {
+        for lang in languages {
+            let pandoc = format!("{{.{}", lang);
+            if info.starts_with(&lang[..]) && ends_word(&info[lang.len()..]) {
+                return Some(Info { pandoc: false, rest: info[lang.len()..].to_string() });
+            } else if info.starts_with(&pandoc) && ends_word(&info[pandoc.len()..]) {
+                return Some(Info { pandoc: true, rest: info[pandoc.len()..].to_string() });
+            }
+        }
+        None
+    }
+
+    /// Recovers the info string from the meta note on a `//@@` line.
+    pub fn from_note(note: &str) -> Info {
+        if let Some(rest) = note.strip_prefix('\\') {
+            return Info { pandoc: false, rest: rest.to_string() };
+        }
+        match note.trim_start().strip_prefix('{') {
+            Some(rest) => Info { pandoc: true, rest: rest.to_string() },
+            None => Info { pandoc: false, rest: note.to_string() },
+        }
+    }
+
+    /// The meta note to carry in the `.rs` file, if there is anything
+    /// beyond the language to carry.
+    pub fn note(&self) -> Option<String> {
+        if self.rest.is_empty() {
+            None
+        } else if self.pandoc {
+            Some(format!(" {{{}", self.rest))
+        } else if self.rest.trim_start().starts_with('{') || self.rest.starts_with(&['\\', '@'][..]) {
+            Some(format!("\\{}", self.rest))
+        } else {
+            Some(self.rest.clone())
+        }
+    }
+
+    /// Renders the info string for a fence, with `lang` as the
+    /// language.
+    pub fn render(&self, lang: &str) -> String {
+        if self.pandoc {
+            format!("{{.{}{}", lang, self.rest)
+        } else {
+            format!("{}{}", lang, self.rest)
+        }
+    }
+
+    /// The attributes following the language: the comma- or
+    /// space-separated words of rustdoc-style strings, or the classes
+    /// (without their `.`) and key-value pairs of pandoc-style ones.
+    pub fn attributes(&self) -> Vec<&str> {
+        let rest = match self.chunk_span() {
+            Some((start, _)) => &self.rest[..start],
+            None => &self.rest[..],
+        };
+        let rest = if self.pandoc { rest.trim_end().trim_end_matches('}') } else { rest };
+        rest.split(|c: char| c == ',' || c.is_whitespace())
+            .map(|a| if self.pandoc { a.trim_start_matches('.') } else { a })
+            .filter(|a| !a.is_empty())
+            .collect()
+    }
+
+    /// The name of the chunk the block defines, if its info string
+    /// ends in `<<name>>=`.
+    pub fn chunk(&self) -> Option<&str> {
+        self.chunk_span().map(|(start, end)| &self.rest[start + 2..end - 3])
+    }
+
+    fn chunk_span(&self) -> Option<(usize, usize)> {
+        let rest = self.rest.trim_end();
+        if !rest.ends_with(">>=") {
+            return None;
+        }
+        rest.rfind("<<").map(|start| (start, rest.len())).filter(|&(start, end)| start + 6 <= end)
+    }
+
+    /// The file the code is written to, if it is not the `.md` file's
+    /// own twin, as given by a `file=` attribute.
+    pub fn file(&self) -> Option<&str> {
+        self.attributes().into_iter()
+            .filter_map(|a| a.strip_prefix("file="))
+            .find(|f| !f.is_empty())
+    }
+
+    pub fn has(&self, attribute: &str) -> bool {
+        self.attributes().contains(&attribute)
+    }
+
+    /// Whether the code is expected to compile; blocks marked
+    /// `ignore` or `compile_fail` are not.
+    pub fn compiles(&self) -> bool {
+        !NOT_COMPILED.iter().any(|a| self.has(a))
+    }
+}
+
+// Whether the language ends just before `rest`, i.e. `rust` in
+// `rust,ignore` but not in `rusty`.
+fn ends_word(rest: &str) -> bool {
+    match rest.chars().next() {
+        Some(c) => !(c.is_alphanumeric() || c == '-' || c == '_'),
+        None => true,
+    }
+}
@@ -3,86 +3,436 @@
 // extern crate env_logger;
 
 extern crate filetime;
+extern crate glob;
+extern crate pulldown_cmark;
+extern crate serde_json;
+extern crate toml;
 extern crate url;
 extern crate walkdir;
+#[cfg(test)]
+extern crate quickcheck;
 
 use filetime::set_file_times;
 use walkdir::{WalkDir};
 
+use std::collections::BTreeMap;
 use std::convert;
+use std::cell::RefCell;
 use std::error::Error as ErrorTrait;
-use std::ffi::OsStr;
 use std::fmt;
 use std::fs::{self, File};
-use std::io::{self, Read, Write};
+use std::io::{self, BufRead, Read, Write};
 use std::ops;
 use std::path::{Path, PathBuf};
-use std::cell::RefCell;
 
+use self::lock::{ContentHash, Lock};
+use self::source_map::SourceMap;
+
+pub use self::chunks::ChunkError;
+pub use self::converter::Converter;
+pub use self::diagnostic::{Diagnostic, Severity};
+pub use self::md2rs::Converter as MdToRs;
+pub use self::rs2md::Converter as RsToMd;
 use self::timestamp::{Timestamp, Timestamped};
 
+pub mod lock;
+pub mod source_map;
+pub mod watch;
 pub mod timestamp;
 
-pub const STAMP: &'static str = "tango.stamp";
-//pub const SRC_DIR: &'static str = "src";
-// pnkfelix wanted the `LIT_DIR` to be `lit/`, but `cargo build`
+mod cargo_messages;
+mod chapters;
+mod chunks;
+mod config_file;
+mod converter;
+mod diagnostic;
+mod diff;
+mod info;
+mod merge;
+mod pieces;
+
+pub const STAMP: &str = "tango.stamp";
+pub const LOCK: &str = "tango.lock";
+// Settings read by the `tango` command from the project root.
+pub const CONFIG_FILE: &str = "tango.toml";
+// Copies of each `.rs` file as of the last sync, used as the common
+// ancestor when both sides of a pair have been edited.
+pub const BASE_DIR: &str = "tango.base";
+// The source map of each generated `.rs` file; see `source_map`.
+pub const MAP_DIR: &str = "tango.map";
+pub const DEFAULT_SRC_DIR: &str = "src";
+// pnkfelix wanted the literate directory to be `lit/`, but `cargo build`
 // currently assumes that *all* build sources live in `src/`. So it
 // is easier for now to just have the two directories be the same.
-//pub const LIT_DIR: &'static str = "src/lit";
+pub const DEFAULT_LIT_DIR: &str = "src";
 
 thread_local! {
-    pub static SRC_DIR: RefCell<String> = RefCell::new("src".to_string());
-    pub static LIT_DIR: RefCell<String> = RefCell::new("src".to_string());
+    // The configuration of `process_root` and `clean`, whose
+    // directories the deprecated functions below get and set.
+    static DEFAULT_CONFIG: RefCell<Config> = RefCell::new(Config::new());
 }
 
-fn set_lit_dir(directory: String) {
-    LIT_DIR.with(|lit_dir| {
-        *lit_dir.borrow_mut() = directory
-    });
+fn default_config() -> Config {
+    DEFAULT_CONFIG.with(|config| config.borrow().clone())
 }
 
-fn set_src_dir(directory: String) {
-    SRC_DIR.with(|src_dir| {
-        *src_dir.borrow_mut() = directory
-    });
+/// Sets the directory for storing the literate .md files, for
+/// `process_root` and `clean` on this thread.
+#[deprecated(note = "use `Config::set_lit_dir` with `process_root_with_config`")]
+pub fn set_lit_dir(directory: String) {
+    DEFAULT_CONFIG.with(|config| { config.borrow_mut().set_lit_dir(directory); });
+}
+
+/// Sets the directory for storing the "source" .rs files, for
+/// `process_root` and `clean` on this thread.
+#[deprecated(note = "use `Config::set_src_dir` with `process_root_with_config`")]
+pub fn set_src_dir(directory: String) {
+    DEFAULT_CONFIG.with(|config| { config.borrow_mut().set_src_dir(directory); });
 }
 
 /// Returns the current directory for storing the literate .md files
+#[deprecated(note = "use `Config::lit_dir`")]
 pub fn get_lit_dir() -> String {
-    LIT_DIR.with(|lit_dir| lit_dir.borrow().clone())
+    DEFAULT_CONFIG.with(|config| config.borrow().lit_dir().to_string())
 }
 
 /// Returns the current directory for storing the "source" .rs files
+#[deprecated(note = "use `Config::src_dir`")]
 pub fn get_src_dir() -> String {
-    SRC_DIR.with(|src_dir| src_dir.borrow().clone())
+    DEFAULT_CONFIG.with(|config| config.borrow().src_dir().to_string())
+}
+
+/// How tango decides which side of a `.rs`/`.md` pair needs to be
+/// regenerated.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SyncMode {
+    /// Compare file contents against the hashes recorded in
+    /// `tango.lock`, falling back to modification times for pairs
+    /// that have no entry there yet.
+    ContentHash,
+    /// Decide solely from modification times and `tango.stamp`.
+    Mtime,
 }
 
+/// Where the playground links for named code blocks point, by default.
+pub const DEFAULT_PLAYGROUND_URL: &str = "https://play.rust-lang.org/";
+pub const DEFAULT_PLAYGROUND_CHANNEL: &str = "nightly";
+
+/// Settings for a tango run.
+///
+/// Settings that are not made through the builder methods are taken
+/// from `[package.metadata.tango]` in the root's `Cargo.toml` and
+/// from `tango.toml` in the root (the latter taking precedence), or
+/// else from the defaults.
+#[derive(Clone, Debug)]
 pub struct Config {
-    src_dir: String,
-    lit_dir: String,
-    rerun_if: bool,
+    root: PathBuf,
+    // `None` means "not set through the builder".
+    src_dir: Option<String>,
+    lit_dir: Option<String>,
+    rerun_if: Option<bool>,
+    sync_mode: Option<SyncMode>,
+    fence_languages: Option<Vec<String>>,
+    playground_url: Option<String>,
+    playground_channel: Option<String>,
+    check_urls: Option<bool>,
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+    // Explicit (rs, md) pairs, relative to the root.
+    mappings: Vec<(PathBuf, PathBuf)>,
+    // `.rs` files assembled from chapters, with the chapters in order,
+    // relative to the root.
+    chapters: Vec<(PathBuf, Vec<PathBuf>)>,
+    read_config_files: bool,
 }
 
 impl Config {
     pub fn new() -> Config {
         Config {
-            src_dir: String::from("src"),
-            lit_dir: String::from("src"),
-            rerun_if: false,
+            root: PathBuf::new(),
+            src_dir: None,
+            lit_dir: None,
+            rerun_if: None,
+            sync_mode: None,
+            fence_languages: None,
+            playground_url: None,
+            playground_channel: None,
+            check_urls: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            mappings: Vec::new(),
+            chapters: Vec::new(),
+            read_config_files: true,
         }
     }
     pub fn set_src_dir(&mut self, new_src_dir: String) -> &mut Config {
-        self.src_dir = new_src_dir;
+        self.src_dir = Some(new_src_dir);
         self
     }
+    /// Returns the directory holding the "source" .rs files
+    pub fn src_dir(&self) -> &str {
+        self.src_dir.as_ref().map_or(DEFAULT_SRC_DIR, |d| &d[..])
+    }
+    /// Returns the directory holding the literate .md files
+    pub fn lit_dir(&self) -> &str {
+        self.lit_dir.as_ref().map_or(DEFAULT_LIT_DIR, |d| &d[..])
+    }
     pub fn set_lit_dir(&mut self, new_lit_dir: String) -> &mut Config {
-        self.lit_dir = new_lit_dir;
+        self.lit_dir = Some(new_lit_dir);
         self
     }
     pub fn emit_rerun_if(&mut self) -> &mut Config {
-        self.rerun_if = true;
+        self.rerun_if = Some(true);
+        self
+    }
+    pub fn set_emit_rerun_if(&mut self, emit: bool) -> &mut Config {
+        self.rerun_if = Some(emit);
+        self
+    }
+    pub fn set_sync_mode(&mut self, mode: SyncMode) -> &mut Config {
+        self.sync_mode = Some(mode);
+        self
+    }
+    /// Sets the info-string languages that mark a fenced code block
+    /// as Rust (by default just `rust`). Generated fences use the
+    /// first one.
+    pub fn set_fence_languages(&mut self, languages: Vec<String>) -> &mut Config {
+        self.fence_languages = Some(languages);
+        self
+    }
+    /// Sets the playground that links for named code blocks point to.
+    pub fn set_playground_url(&mut self, url: String) -> &mut Config {
+        self.playground_url = Some(url);
+        self
+    }
+    /// Sets the release channel (`stable`, `beta` or `nightly`)
+    /// requested by playground links.
+    pub fn set_playground_channel(&mut self, channel: String) -> &mut Config {
+        self.playground_channel = Some(channel);
+        self
+    }
+    /// Whether to warn when a playground link in a `.md` file does
+    /// not match the code block it names (the default).
+    pub fn check_playground_urls(&mut self, check: bool) -> &mut Config {
+        self.check_urls = Some(check);
+        self
+    }
+    /// Whether to read settings from `tango.toml` and `Cargo.toml`
+    /// (the default).
+    pub fn read_config_files(&mut self, read: bool) -> &mut Config {
+        self.read_config_files = read;
+        self
+    }
+    /// Sets the project root, against which the stamp, lock, source
+    /// and literate directories are resolved. Defaults to the current
+    /// directory.
+    pub fn set_root<P: Into<PathBuf>>(&mut self, root: P) -> &mut Config {
+        self.root = root.into();
+        self
+    }
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    /// Restricts processing to pairs whose `.rs` or `.md` path
+    /// (relative to the root) matches one of the include patterns.
+    /// With no include patterns, every pair is included.
+    pub fn include(&mut self, pattern: &str) -> Result<&mut Config> {
+        self.include.push((compile_pattern(pattern))?);
+        Ok(self)
+    }
+    /// Skips pairs whose `.rs` or `.md` path matches the pattern.
+    pub fn exclude(&mut self, pattern: &str) -> Result<&mut Config> {
+        self.exclude.push((compile_pattern(pattern))?);
+        Ok(self)
+    }
+    /// Pairs the `.rs` file `rs` with the `.md` file `md` (both
+    /// relative to the root), e.g. `src/lib.rs` with `README.md`.
+    /// Mapped files need not lie under the source and literate
+    /// directories, and are processed regardless of the include and
+    /// exclude patterns.
+    pub fn map_file<P: Into<PathBuf>, Q: Into<PathBuf>>(&mut self, rs: P, md: Q) -> &mut Config {
+        let (rs, md) = (rs.into(), md.into());
+        self.mappings.retain(|(r, m)| *r != rs && *m != md);
+        self.mappings.push((rs, md));
+        self
+    }
+    /// Assembles the `.rs` file `rs` from the `.md` files `chapters`,
+    /// in order (all relative to the root), and splits it back into
+    /// them. Like mapped files, the chapters need not lie under the
+    /// literate directory, and are processed regardless of the
+    /// include and exclude patterns.
+    pub fn map_chapters<P: Into<PathBuf>, Q: Into<PathBuf>>(&mut self, rs: P, chapters: Vec<Q>) -> &mut Config {
+        let rs = rs.into();
+        self.chapters.retain(|(r, _)| *r != rs);
+        if !chapters.is_empty() {
+            self.chapters.push((rs, chapters.into_iter().map(Into::into).collect()));
+        }
         self
     }
+    /// Applies the settings in the TOML file at `path`, as if they
+    /// had been made through the builder methods.
+    pub fn read_config_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Config> {
+        let path = path.as_ref();
+        let text = (read_file(path))?;
+        (config_file::apply(self, &text).map_err(|e| config_file::in_file(path, e)))?;
+        Ok(self)
+    }
+    /// Fills in the settings not made through the builder from the
+    /// configuration files under the root, if reading them is
+    /// enabled. This is done by every entry point that takes a
+    /// `Config` by value.
+    pub fn load_files(self) -> Result<Config> {
+        if !self.read_config_files {
+            return Ok(self);
+        }
+        let mut base = Config::new();
+        let cargo_toml = self.resolve("Cargo.toml");
+        if cargo_toml.exists() {
+            let text = (read_file(&cargo_toml))?;
+            (config_file::apply_cargo_metadata(&mut base, &text)
+             .map_err(|e| config_file::in_file(&cargo_toml, e)))?;
+        }
+        let tango_toml = self.resolve(CONFIG_FILE);
+        if tango_toml.exists() {
+            (base.read_config_file(tango_toml))?;
+        }
+        Ok(self.over(base))
+    }
+
+    // Layers the settings made on `self` over those made on `base`.
+    fn over(self, base: Config) -> Config {
+        let mut mappings = base.mappings;
+        for (rs, md) in self.mappings {
+            mappings.retain(|(r, m)| *r != rs && *m != md);
+            mappings.push((rs, md));
+        }
+        let mut chapters = base.chapters;
+        for (rs, mds) in self.chapters {
+            chapters.retain(|(r, _)| *r != rs);
+            chapters.push((rs, mds));
+        }
+        Config {
+            root: self.root,
+            src_dir: self.src_dir.or(base.src_dir),
+            lit_dir: self.lit_dir.or(base.lit_dir),
+            rerun_if: self.rerun_if.or(base.rerun_if),
+            sync_mode: self.sync_mode.or(base.sync_mode),
+            fence_languages: self.fence_languages.or(base.fence_languages),
+            playground_url: self.playground_url.or(base.playground_url),
+            playground_channel: self.playground_channel.or(base.playground_channel),
+            check_urls: self.check_urls.or(base.check_urls),
+            include: base.include.into_iter().chain(self.include).collect(),
+            exclude: base.exclude.into_iter().chain(self.exclude).collect(),
+            mappings,
+            chapters,
+            read_config_files: false,
+        }
+    }
+
+    fn rerun_if(&self) -> bool {
+        self.rerun_if.unwrap_or(false)
+    }
+    fn sync_mode(&self) -> SyncMode {
+        self.sync_mode.unwrap_or(SyncMode::ContentHash)
+    }
+    fn fence_languages(&self) -> Vec<String> {
+        match self.fence_languages {
+            Some(ref languages) if !languages.is_empty() => languages.clone(),
+            _ => vec!["rust".to_string()],
+        }
+    }
+    fn playground_url(&self) -> &str {
+        self.playground_url.as_ref().map_or(DEFAULT_PLAYGROUND_URL, |u| &u[..])
+    }
+    fn playground_channel(&self) -> &str {
+        self.playground_channel.as_ref().map_or(DEFAULT_PLAYGROUND_CHANNEL, |c| &c[..])
+    }
+    fn check_urls(&self) -> bool {
+        self.check_urls.unwrap_or(true)
+    }
+
+    fn resolve<P: AsRef<Path>>(&self, p: P) -> PathBuf {
+        self.root.join(p)
+    }
+    // The path of the file that `file=` blocks naming `file` write
+    // to, if they may: it must be a `.rs` file under the source
+    // directory, named without `..`, so that no `.md` file can have
+    // tango write (or remove) a file anywhere else.
+    fn piece_path(&self, file: &str) -> Option<PathBuf> {
+        use std::path::Component;
+        let p = Path::new(file);
+        let plain = p.components().all(|c| matches!(c, Component::Normal(_)));
+        let src: PathBuf = Path::new(self.src_dir()).components().filter(|&c| c != Component::CurDir).collect();
+        if plain && p.extension().is_some_and(|ext| ext == "rs") && p.starts_with(src) {
+            Some(self.resolve(p))
+        } else {
+            None
+        }
+    }
+    // The path of `p` relative to the root; this is how paths are
+    // recorded in `tango.lock`, so that it does not depend on where
+    // the project lives.
+    fn relative<'a>(&self, p: &'a Path) -> &'a Path {
+        p.strip_prefix(&self.root).unwrap_or(p)
+    }
+    // The twin of `p` under an explicit mapping, if there is one. The
+    // twin of a `.rs` file assembled from chapters is its first
+    // chapter.
+    fn mapped_twin(&self, p: &Path) -> Option<PathBuf> {
+        let rel = self.relative(p);
+        let mapped = self.mappings.iter().filter_map(|(rs, md)| {
+            if rel == rs.as_path() {
+                Some(self.resolve(md))
+            } else if rel == md.as_path() {
+                Some(self.resolve(rs))
+            } else {
+                None
+            }
+        }).next();
+        mapped.or_else(|| self.chapters.iter().filter_map(|(rs, mds)| {
+            if rel == rs.as_path() {
+                Some(self.resolve(&mds[0]))
+            } else if mds.iter().any(|md| rel == md.as_path()) {
+                Some(self.resolve(rs))
+            } else {
+                None
+            }
+        }).next())
+    }
+    // The chapters after `md`, if it is the first chapter of a `.rs`
+    // file assembled from chapters.
+    fn later_chapters(&self, md: &Path) -> Vec<PathBuf> {
+        let rel = self.relative(md);
+        match self.chapters.iter().find(|&(_, mds)| rel == mds[0].as_path()) {
+            Some((_, mds)) => mds[1..].iter().map(|md| self.resolve(md)).collect(),
+            None => Vec::new(),
+        }
+    }
+    // Whether the include and exclude patterns select the pair.
+    fn selects(&self, rs: &Path, md: &Path) -> bool {
+        if self.mapped_twin(rs).is_some() {
+            return true;
+        }
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+        let matches = |patterns: &[glob::Pattern], p: &Path| {
+            patterns.iter().any(|pat| pat.matches_path_with(self.relative(p), options))
+        };
+        (self.include.is_empty() || matches(&self.include, rs) || matches(&self.include, md))
+            && !matches(&self.exclude, rs) && !matches(&self.exclude, md)
+    }
+    // Where the copy of `rs` as of the last sync is kept.
+    fn base_path(&self, rs: &Path) -> PathBuf {
+        self.resolve(BASE_DIR).join(self.relative(rs))
+    }
+    // Where the source map of `rs` is kept.
+    fn map_path(&self, rs: &Path) -> PathBuf {
+        let mut p = self.resolve(MAP_DIR).join(self.relative(rs)).into_os_string();
+        p.push(".map");
+        PathBuf::from(p)
+    }
 
 }
 
@@ -94,29 +444,119 @@ pub enum Error {
     MtimeError(PathBuf),
     ConcurrentUpdate { path_buf: PathBuf, old_time: mtime, new_time: mtime },
     Warnings(Vec<Warning>),
+    OutOfSync(Vec<Mismatch>),
+    ConfigError(String),
+    ChunkError(ChunkError),
+    /// The problems found converting a file, placed in it.
+    Diagnostics(Vec<Diagnostic>),
+    /// The `.rs` files of pairs whose merge left conflict markers in
+    /// both files; the pairs stay out of `tango.lock` until a later run
+    /// finds the markers resolved.
+    MergeConflicts(Vec<PathBuf>),
 }
 
-#[derive(Debug)]
+/// Something amiss in the input of a conversion, which does not stop
+/// it. Each is at a (1-based) line and column of the input, the column
+/// counting characters.
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Warning {
-    EncodedUrlMismatch { actual: String, expect: String }
+    /// The playground link on the given line of the `.md` file does
+    /// not match the code block it follows (touching the `.md` file and
+    /// rerunning tango brings it up to date).
+    EncodedUrlMismatch { actual: String, expect: String, line: usize, column: usize },
+    /// A meta note in the `.rs` file was followed by another, on the
+    /// given line, before any code, and so dropped.
+    DiscardedMetaNote { discarded: String, kept: String, line: usize, column: usize },
+    /// The line of Rust code on the given line of the `.md` file starts
+    /// with `//@`, so the `.rs` file would read it back as Markdown.
+    MarkerInCode { code: String, line: usize, column: usize },
+    /// The given line of the `.rs` file is a tango comment within the
+    /// expansion of a chunk, which holds only code; it is left out of
+    /// the chunk.
+    CommentInExpansion { comment: String, line: usize, column: usize },
+    /// The expansion of a chunk starting on the given line of the
+    /// `.rs` file has no end marker, so its lines are taken as they
+    /// are.
+    UnclosedExpansion { name: String, line: usize, column: usize },
+    /// The Rust code block opened on the given line of the `.md` file
+    /// is never closed, so it runs to the end of the file.
+    UnclosedFence { line: usize, column: usize },
+    /// The meta note on the given line of the `.rs` file is followed by
+    /// Markdown, or nothing, rather than the code it is for; it is
+    /// dropped.
+    OrphanedMetaNote { note: String, line: usize, column: usize },
 }
 
-impl fmt::Display for Warning {
-    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+impl Warning {
+    /// The line of the input that the warning is about.
+    pub fn line(&self) -> usize {
+        self.position().0
+    }
+
+    /// The column of the line that the warning is about.
+    pub fn column(&self) -> usize {
+        self.position().1
+    }
+
+    fn position(&self) -> (usize, usize) {
         match *self {
-            Warning::EncodedUrlMismatch { ref actual, ref expect } => {
-                write!(w, "mismatch between encoded url, expect: {} actual: {}",
-                       expect, actual)
-            }
+            Warning::EncodedUrlMismatch { line, column, .. } |
+            Warning::DiscardedMetaNote { line, column, .. } |
+            Warning::MarkerInCode { line, column, .. } |
+            Warning::CommentInExpansion { line, column, .. } |
+            Warning::UnclosedExpansion { line, column, .. } |
+            Warning::UnclosedFence { line, column } |
+            Warning::OrphanedMetaNote { line, column, .. } => (line, column),
+        }
+    }
+
+    /// A stable, machine-readable name for the kind of warning.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Warning::EncodedUrlMismatch { .. } => "url-mismatch",
+            Warning::DiscardedMetaNote { .. } => "discarded-meta-note",
+            Warning::MarkerInCode { .. } => "marker-in-code",
+            Warning::CommentInExpansion { .. } => "comment-in-expansion",
+            Warning::UnclosedExpansion { .. } => "unclosed-expansion",
+            Warning::UnclosedFence { .. } => "unclosed-fence",
+            Warning::OrphanedMetaNote { .. } => "orphaned-meta-note",
         }
     }
+
+    /// The warning as a diagnostic, in no file as yet.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let message = match *self {
+            Warning::EncodedUrlMismatch { ref actual, ref expect, .. } =>
+                format!("mismatch between encoded url, expect: {} actual: {}", expect, actual),
+            Warning::DiscardedMetaNote { ref discarded, ref kept, .. } =>
+                format!("discarding meta note {} for {}", discarded, kept),
+            Warning::MarkerInCode { ref code, .. } =>
+                format!("code would be read back from the .rs file as a tango comment: {}", code),
+            Warning::CommentInExpansion { ref comment, .. } =>
+                format!("tango comment within a chunk expansion left out: {}", comment),
+            Warning::UnclosedExpansion { ref name, .. } =>
+                format!("expansion of chunk <<{}>> is never closed", name),
+            Warning::UnclosedFence { .. } =>
+                "code block is never closed".to_string(),
+            Warning::OrphanedMetaNote { ref note, .. } =>
+                format!("meta note {} is not followed by code, so is dropped", note),
+        };
+        Diagnostic::new(Severity::Warning, self.code(), message, self.line(), self.column())
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}", self.diagnostic())
+    }
 }
 
 impl From<md2rs::Exception> for Error {
     fn from(e: md2rs::Exception) -> Self {
         match e {
             md2rs::Exception::IoError(e) => Error::IoError(e),
-            md2rs::Exception::Warnings(w) => Error::Warnings(w),
+            md2rs::Exception::Warnings(w) => Error::Warnings(w.into_iter().map(|(_, w)| w).collect()),
+            md2rs::Exception::Chunks(_, e) => Error::ChunkError(e),
         }
     }
 }
@@ -135,8 +575,33 @@ impl fmt::Display for Error {
                 write!(w, "concurrent update during `tango` to source file {}",
                        path_buf.to_string_lossy()),
             Error::Warnings(ref warnings) => {
-                for warn in warnings {
-                    (write!(w, "WARNING: {}", warn))?;
+                for (i, warn) in warnings.iter().enumerate() {
+                    (write!(w, "{}{}", if i > 0 { "\n" } else { "" }, warn))?;
+                }
+                Ok(())
+            }
+            Error::Diagnostics(ref diagnostics) => {
+                for (i, d) in diagnostics.iter().enumerate() {
+                    (write!(w, "{}{}", if i > 0 { "\n\n" } else { "" }, d))?;
+                }
+                Ok(())
+            }
+            Error::ConfigError(ref msg) =>
+                write!(w, "configuration error: {}", msg),
+            Error::ChunkError(ref e) =>
+                write!(w, "code chunk error: {}", e),
+            Error::MergeConflicts(ref paths) => {
+                (write!(w, "{} merge(s) left conflict markers; resolve them in either file and rerun `tango`:",
+                        paths.len()))?;
+                for p in paths {
+                    (write!(w, "\n    {}", p.display()))?;
+                }
+                Ok(())
+            }
+            Error::OutOfSync(ref mismatches) => {
+                (write!(w, "{} file(s) out of sync:", mismatches.len()))?;
+                for m in mismatches {
+                    (write!(w, "\n    {}", m))?;
                 }
                 Ok(())
             }
@@ -151,7 +616,12 @@ impl ErrorTrait for Error {
             Error::CheckInputError { ref error, .. } => {
                 Some(error)
             }
+            Error::ChunkError(ref e) => Some(e),
             Error::Warnings(_) |
+            Error::Diagnostics(_) |
+            Error::OutOfSync(_) |
+            Error::MergeConflicts(_) |
+            Error::ConfigError(_) |
             Error::MtimeError(_) |
             Error::ConcurrentUpdate { .. } => None,
         }
@@ -172,6 +642,12 @@ impl convert::From<walkdir::Error> for Error {
 
 pub type Result<X> = std::result::Result<X, Error>;
 
+fn compile_pattern(pattern: &str) -> Result<glob::Pattern> {
+    glob::Pattern::new(pattern).map_err(|e| {
+        Error::ConfigError(format!("invalid pattern `{}`: {}", pattern, e))
+    })
+}
+
 #[allow(non_camel_case_types)]
 pub type mtime = Timestamp;
 
@@ -201,39 +677,78 @@ impl Mtime for fs::DirEntry {
     }
 }
 impl Mtime for RsPath {
+    // The pieces written from the same `.md` file count as part of the
+    // twin.
     fn modified(&self) -> Result<MtimeResult> {
-        if self.0.exists() {
-            let f = (File::open(&self.0))?;
-            f.modified()
-        } else {
-            Ok(MtimeResult::NonExistant)
-        }
+        group_modified(&self.0, &self.1)
     }
 }
 impl Mtime for MdPath {
+    // As do the later chapters of a `.rs` file assembled from
+    // chapters, for the first one.
     fn modified(&self) -> Result<MtimeResult> {
-        if self.0.exists() {
-            let f = (File::open(&self.0))?;
-            f.modified()
-        } else {
-            Ok(MtimeResult::NonExistant)
+        group_modified(&self.0, &self.1)
+    }
+}
+
+// The modification time of a file and the others synced along with
+// it: that of the newest of them, or `NonExistant` if the file itself
+// does not exist.
+fn group_modified(p: &Path, others: &[PathBuf]) -> Result<MtimeResult> {
+    if !p.exists() {
+        return Ok(MtimeResult::NonExistant);
+    }
+    let mut newest = (File::open(p))?.modified()?;
+    for p in others {
+        if p.exists() {
+            let t = (File::open(p))?.modified()?;
+            if let (MtimeResult::Modified(a), MtimeResult::Modified(b)) = (newest, t) {
+                if b > a { newest = t; }
+            }
         }
     }
+    Ok(newest)
+}
+
+// The content hash that `tango.lock` records for a file.
+trait Hashed { fn content_hash(&self) -> Result<ContentHash>; }
+impl Hashed for RsPath {
+    fn content_hash(&self) -> Result<ContentHash> {
+        group_hash(&self.0, &self.1)
+    }
+}
+impl Hashed for MdPath {
+    fn content_hash(&self) -> Result<ContentHash> {
+        group_hash(&self.0, &self.1)
+    }
+}
+
+// The content hash of a file and the others synced along with it;
+// for a file on its own, this is just the hash of the file.
+fn group_hash(p: &Path, others: &[PathBuf]) -> Result<ContentHash> {
+    if others.is_empty() {
+        return Ok((ContentHash::of_file(p))?);
+    }
+    let mut bytes = (fs::read(p))?;
+    for p in others {
+        bytes.push(0);
+        if p.exists() {
+            bytes.extend((fs::read(p))?);
+        }
+    }
+    Ok(ContentHash::of_bytes(&bytes))
 }
 
 pub fn process_root_with_config(config: Config) -> Result<()> {
+    let config = (config.load_files())?;
     //let _root = (std::env::current_dir())?;
     //println!("Tango is running from: {:?}", root);
     //std::env::set_current_dir(_root).unwrap();
-    set_lit_dir(config.lit_dir);
-    set_src_dir(config.src_dir);
-    let emit_rerun_if = config.rerun_if;
-
-    let stamp_path = Path::new(STAMP);
+    let stamp_path = config.resolve(STAMP);
     if stamp_path.exists() {
-        process_with_stamp((File::open(stamp_path))?, emit_rerun_if)
+        process_with_stamp((File::open(stamp_path))?, config)
     } else {
-        process_without_stamp(emit_rerun_if)
+        process_without_stamp(config)
     }
 }
 
@@ -242,15 +757,411 @@ pub fn process_root() -> Result<()> {
     //let _root = (std::env::current_dir())?;
     // println!("Tango is running from: {:?}", _root);
 
-    let emit_rerun_if = false;
-    let stamp_path = Path::new(STAMP);
-    if stamp_path.exists() {
-        process_with_stamp((File::open(stamp_path))?, emit_rerun_if)
+    process_root_with_config(default_config())
+}
+
+/// Which way a transform converts.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction { RsToMd, MdToRs }
+
+/// What a sync would do with a transform.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Regenerate the target from the source.
+    Generate,
+    /// Leave the target alone.
+    Skip,
+    /// Only bring the target's timestamp up to the source's.
+    Retime,
+    /// Three-way merge the source and target.
+    Merge,
+    /// Stop the sync with an error.
+    Refuse,
+}
+
+/// Why a transform was given its `Action`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Reason {
+    TargetMissing,
+    SourceChangedSinceLock,
+    TargetChangedSinceLock,
+    BothChangedSinceLock,
+    UnchangedSinceLock,
+    StaleTimestamp,
+    SourceNewer,
+    TargetNewer,
+    SameTimestamp,
+    Refused(String),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reason::TargetMissing => write!(w, "target does not exist"),
+            Reason::SourceChangedSinceLock => write!(w, "source changed since last sync"),
+            Reason::TargetChangedSinceLock => write!(w, "target changed since last sync"),
+            Reason::BothChangedSinceLock => write!(w, "both sides changed since last sync"),
+            Reason::UnchangedSinceLock => write!(w, "unchanged since last sync"),
+            Reason::StaleTimestamp => write!(w, "contents in sync, but target is older than source"),
+            Reason::SourceNewer => write!(w, "source is newer than target"),
+            Reason::TargetNewer => write!(w, "target is newer than source"),
+            Reason::SameTimestamp => write!(w, "source and target have the same timestamp"),
+            Reason::Refused(ref why) => write!(w, "{}", why),
+        }
+    }
+}
+
+/// One transform considered by a sync, as reported by
+/// `plan_with_config`.
+#[derive(Clone, Debug)]
+pub struct PlannedTransform {
+    pub direction: Direction,
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub source_time: mtime,
+    pub target_time: Option<mtime>,
+    pub action: Action,
+    pub reason: Reason,
+}
+
+impl fmt::Display for PlannedTransform {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.action {
+            Action::Generate => "generate",
+            Action::Skip => "skip",
+            Action::Retime => "retime",
+            Action::Merge => "merge",
+            Action::Refuse => "refuse",
+        };
+        (writeln!(w, "{:<8} {} -> {}: {}", action,
+                  self.source.display(), self.target.display(), self.reason))?;
+        (writeln!(w, "         source: {}", self.source_time.date_fulltime_badly()))?;
+        match self.target_time {
+            Some(t) => write!(w, "         target: {}", t.date_fulltime_badly()),
+            None => write!(w, "         target: (missing)"),
+        }
+    }
+}
+
+/// Works out what a sync would do, without writing anything: every
+/// transform that `process_root_with_config` would consider is
+/// returned along with the action it would take and why.
+pub fn plan_with_config(config: Config) -> Result<Vec<PlannedTransform>> {
+    let config = (config.load_files())?;
+    let stamp_path = config.resolve(STAMP);
+    let stamp = if stamp_path.exists() { Some((File::open(stamp_path))?) } else { None };
+    let mut c = (Context::new(stamp, config))?;
+    c.emit_rerun_if = false;
+    c.planning = true;
+    (c.gather_inputs())?;
+    Ok(c.plan)
+}
+
+/// A target file whose contents differ from what converting its
+/// source would produce.
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    /// Unified diff from the on-disk target to the generated content.
+    pub diff: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{} is out of sync with {}",
+               self.target.display(), self.source.display())
+    }
+}
+
+/// Checks that every `.rs`/`.md` pair is in sync, for use in CI.
+///
+/// Each source is converted in memory and the result compared against
+/// the file on disk; no file is written, and neither timestamps nor
+/// `tango.stamp` are consulted. A pair is in sync if either file is
+/// exactly what converting the other would produce. Pairs that are
+/// not are reported via `Error::OutOfSync`.
+pub fn check_with_config(config: Config) -> Result<()> {
+    let config = (config.load_files())?;
+    let lock_path = config.resolve(LOCK);
+    let lock = if lock_path.exists() { Some((Lock::read(lock_path))?) } else { None };
+    let mut c = (Context::new(None, config))?;
+    c.emit_rerun_if = false;
+    c.planning = true;
+    (c.gather_inputs())?;
+
+    let mut mismatches = Vec::new();
+    for (rs, md) in &c.pairs {
+        let recorded = lock.as_ref().and_then(|l| {
+            l.lookup(c.config.relative(rs), c.config.relative(md))
+        });
+        let rs = (RsPath::new(rs.clone(), &c.config))?;
+        let md = (MdPath::new(md.clone(), &c.config))?;
+        if let Some(m) = (check_pair(&c.config, &rs, &md, recorded))? {
+            mismatches.push(m);
+        }
+    }
+    if mismatches.is_empty() {
+        Ok(())
     } else {
-        process_without_stamp(emit_rerun_if)
+        Err(Error::OutOfSync(mismatches))
     }
 }
 
+// `recorded` holds the hashes of `rs` and `md` in `tango.lock`, if any.
+fn check_pair(config: &Config, rs: &RsPath, md: &MdPath, recorded: Option<(ContentHash, ContentHash)>)
+              -> Result<Option<Mismatch>> {
+    fn mismatch(source: &Path, target: &Path, actual: &str, expect: &str) -> Mismatch {
+        let a = target.display().to_string();
+        let b = format!("{} (generated from {})", target.display(), source.display());
+        Mismatch {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            diff: diff::unified(&a, actual, &b, expect),
+        }
+    }
+    // Finds the first file of a group whose content differs from that
+    // generated for it; the files of the group that nothing was
+    // generated for are expected to be empty.
+    fn stale(generated: Vec<(PathBuf, String)>, group: &[PathBuf])
+             -> Result<Option<(PathBuf, String, String)>> {
+        let missing: Vec<(PathBuf, String)> = group.iter()
+            .filter(|p| !generated.iter().any(|(q, _)| q == *p))
+            .map(|p| (p.clone(), String::new()))
+            .collect();
+        for (p, expect) in generated.into_iter().chain(missing) {
+            let actual = if p.exists() { (read_file(&p))? } else { String::new() };
+            if actual != expect {
+                return Ok(Some((p, actual, expect)));
+            }
+        }
+        Ok(None)
+    }
+    let rs_from_md = |md: &MdPath| -> Result<Vec<(PathBuf, String)>> {
+        let (content, pieces) = (md2rs_group(config, md, rs))?;
+        Ok(Some((rs.to_path_buf(), content)).into_iter().chain(pieces).collect())
+    };
+
+    Ok(match (rs.exists(), md.exists()) {
+        (false, false) => None,
+        (true, false) => (stale((rs2md_group(config, rs, md))?, &md.1))?
+            .map(|(p, actual, expect)| mismatch(rs, &p, &actual, &expect)),
+        (false, true) => (stale((rs_from_md(md))?, &rs.1))?
+            .map(|(p, actual, expect)| mismatch(md, &p, &actual, &expect)),
+        (true, true) => {
+            // A pair is in sync if either side, with all the files of
+            // its group, is what converting the other would produce.
+            let rs_stale = (stale((rs_from_md(md))?, &rs.1))?;
+            let md_stale = (stale((rs2md_group(config, rs, md))?, &md.1))?;
+            match (rs_stale, md_stale) {
+                (None, _) | (_, None) => None,
+                (Some((rs_p, rs_actual, rs_expect)), Some((md_p, md_actual, md_expect))) => {
+                    // Report the direction a sync would most plausibly
+                    // take: from the `.rs` if only it has been edited
+                    // since the last sync, and from the `.md` otherwise.
+                    let rs_only_changed = match recorded {
+                        Some((rs_hash, md_hash)) =>
+                            (rs.content_hash())? != rs_hash && (md.content_hash())? == md_hash,
+                        None => false,
+                    };
+                    if rs_only_changed {
+                        Some(mismatch(rs, &md_p, &md_actual, &md_expect))
+                    } else {
+                        Some(mismatch(md, &rs_p, &rs_actual, &rs_expect))
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// The state of one `.rs`/`.md` pair relative to `tango.lock`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PairState {
+    RsOnly,
+    MdOnly,
+    /// Both files exist, but `tango.lock` has no entry for them.
+    Untracked,
+    InSync,
+    RsChanged,
+    MdChanged,
+    BothChanged,
+}
+
+#[derive(Clone, Debug)]
+pub struct PairStatus {
+    pub rs_path: PathBuf,
+    pub md_path: PathBuf,
+    pub state: PairState,
+}
+
+/// Reports the state of every `.rs`/`.md` pair, as compared against
+/// the content hashes recorded in `tango.lock`.
+pub fn status_with_config(config: Config) -> Result<Vec<PairStatus>> {
+    let config = (config.load_files())?;
+    let lock_path = config.resolve(LOCK);
+    let lock = if lock_path.exists() { (Lock::read(lock_path))? } else { Lock::new() };
+
+    let mut pairs: Vec<(RsPath, MdPath)> = Vec::new();
+    let (rs_files, md_files) = (candidates(&config, false))?;
+    for rs in rs_files {
+        let md = (rs.to_md(&config))?;
+        pairs.push((rs, md));
+    }
+    for md in md_files {
+        let rs = (md.to_rs(&config))?;
+        if !pairs.iter().any(|(r, _)| r.0 == rs.0) {
+            pairs.push((rs, md));
+        }
+    }
+    pairs.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+
+    let mut statuses = Vec::new();
+    for (rs, md) in pairs {
+        let state = match (rs.exists(), md.exists()) {
+            (true, false) => PairState::RsOnly,
+            (false, true) => PairState::MdOnly,
+            (false, false) => continue,
+            (true, true) => match lock.lookup(config.relative(&rs), config.relative(&md)) {
+                None => PairState::Untracked,
+                Some((rs_hash, md_hash)) => {
+                    let rs_changed = (rs.content_hash())? != rs_hash;
+                    let md_changed = (md.content_hash())? != md_hash;
+                    match (rs_changed, md_changed) {
+                        (false, false) => PairState::InSync,
+                        (true, false) => PairState::RsChanged,
+                        (false, true) => PairState::MdChanged,
+                        (true, true) => PairState::BothChanged,
+                    }
+                }
+            },
+        };
+        statuses.push(PairStatus { rs_path: rs.0, md_path: md.0, state });
+    }
+    Ok(statuses)
+}
+
+/// Removes the state that tango keeps between runs (`tango.stamp`,
+/// `tango.lock`, the saved merge bases and the source maps). The `.rs`
+/// and `.md` files themselves are left alone.
+pub fn clean() -> Result<()> {
+    clean_with_config(default_config())
+}
+
+pub fn clean_with_config(config: Config) -> Result<()> {
+    let config = (config.load_files())?;
+    for file in &[STAMP, LOCK] {
+        let path = config.resolve(file);
+        if path.exists() {
+            (fs::remove_file(path))?;
+        }
+    }
+    for dir in &[BASE_DIR, MAP_DIR] {
+        let path = config.resolve(dir);
+        if path.exists() {
+            (fs::remove_dir_all(path))?;
+        }
+    }
+    Ok(())
+}
+
+/// A position in a file, with the line and column counted from 1.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Location {
+    /// Parses `<path>:<line>:<col>`, or `<path>:<line>` for the start
+    /// of the line.
+    pub fn parse(s: &str) -> Option<Location> {
+        let number = |s: &str| s.parse::<usize>().ok().filter(|&n| n > 0);
+        let i = (s.rfind(':'))?;
+        let (rest, last) = (&s[..i], (number(&s[i + 1..]))?);
+        let with_line = rest.rfind(':')
+            .and_then(|i| number(&rest[i + 1..]).map(|line| (&rest[..i], line)));
+        let (path, line, col) = match with_line {
+            Some((path, line)) => (path, line, last),
+            None => (rest, last, 1),
+        };
+        if path.is_empty() {
+            return None;
+        }
+        Some(Location { path: PathBuf::from(path), line, col })
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}:{}:{}", self.path.display(), self.line, self.col)
+    }
+}
+
+/// Translates a position in a `.rs` file generated by tango into the
+/// position in the `.md` file it came from, or a position in a `.md`
+/// file into the first position generated from it, using the source
+/// maps recorded by the last sync. Paths are relative to the root.
+/// Returns `None` if no map covers the position.
+pub fn map_location(config: Config, from: &Location) -> Result<Option<Location>> {
+    let config = (config.load_files())?;
+    let path = config.relative(&from.path);
+    if path.rs_extension() {
+        let map_path = config.map_path(path);
+        if !map_path.exists() {
+            return Ok(None);
+        }
+        let map = (SourceMap::read(map_path))?;
+        return Ok(map.to_md(from.line, from.col).map(|(md, line, col)| {
+            Location { path: config.resolve(md), line, col }
+        }));
+    }
+
+    let map_dir = config.resolve(MAP_DIR);
+    if !map_dir.exists() {
+        return Ok(None);
+    }
+    let mut maps = Vec::new();
+    for ent in WalkDir::new(&map_dir) {
+        let ent = (ent)?;
+        if ent.file_type().is_file() && ent.path().extension() == Some("map".as_ref()) {
+            maps.push(ent.path().to_path_buf());
+        }
+    }
+    maps.sort();
+    for map_path in maps {
+        let map = (SourceMap::read(&map_path))?;
+        if let Some((line, col)) = map.to_rs(path, from.line, from.col) {
+            let rs = map_path.strip_prefix(&map_dir).unwrap_or(&map_path).with_extension("");
+            return Ok(Some(Location { path: config.resolve(rs), line, col }));
+        }
+    }
+    Ok(None)
+}
+
+/// Copies cargo's JSON messages (as from `cargo build
+/// --message-format=json`) from `source` to `target`, with each
+/// diagnostic span in a generated `.rs` file pointing instead at the
+/// `.md` location it came from, per the recorded source maps. All
+/// else is copied unchanged.
+pub fn rewrite_cargo_diagnostics<R:BufRead, W:Write>(config: Config, source: R, target: W) -> Result<()> {
+    let config = (config.load_files())?;
+    let mut maps: BTreeMap<PathBuf, Option<SourceMap>> = BTreeMap::new();
+    let mut lookup = |rs: &Path, line: usize, col: usize| {
+        let rs = config.relative(rs).to_path_buf();
+        if !rs.rs_extension() {
+            return None;
+        }
+        let map = maps.entry(rs.clone()).or_insert_with(|| SourceMap::read(config.map_path(&rs)).ok());
+        map.as_ref()
+            .and_then(|map| map.to_md(line, col))
+            .map(|(md, line, col)| (config.resolve(md), line, col))
+    };
+    (cargo_messages::rewrite(source, target, &mut lookup))?;
+    Ok(())
+}
+
 // Both of the functions below have the same basic outline:
 //
 // 1. gather_inputs(): Build up a list of potential transforms based
@@ -265,6 +1176,16 @@ pub fn process_root() -> Result<()> {
 // 4. adjust_stamp_timestamp(): Update the `tango.stamp` file to the
 //    youngest timestamp we saw, creating the file if necessary.
 //
+// 5. update_lock(): Record the content hash of every pair in
+//    `tango.lock`, so that the next run can tell which side changed
+//    without consulting timestamps.
+//
+// 6. record_maps(): Write the source map of every `.rs` file whose
+//    map is missing or out of date with its `.md` file.
+//
+// 7. report_conflicts(): Fail if a merge left conflict markers, once
+//    everything else has been brought up to date.
+//
 // The reason there are two functions is that in one case we have a
 // pre-existing `tango.stamp` that we want to compare against during
 // `generate_content()` (to guard against diverging {source, target}
@@ -274,50 +1195,75 @@ pub fn process_root() -> Result<()> {
 // (It probably wouldn't be hard to unify the two functions into a
 //  single method on the `Context`, though.)
 
-fn process_with_stamp(stamp: File, emit_rerun_if: bool) -> Result<()> {
-    println!("\n\nemit rerun if: {:?}\n\n", emit_rerun_if);
+fn process_with_stamp(stamp: File, config: Config) -> Result<()> {
+    println!("\n\nemit rerun if: {:?}\n\n", config.rerun_if());
     if let Ok(MtimeResult::Modified(ts)) = stamp.modified() {
         println!("Rerunning tango; last recorded run was stamped: {}",
                  ts.date_fulltime_badly());
     } else {
         panic!("why are we trying to process_with_stamp when given: {:?}", stamp);
     }
-    let mut c = (Context::new(Some(stamp)))?;
-    c.emit_rerun_if = emit_rerun_if;
+    let mut c = (Context::new(Some(stamp), config))?;
     (c.gather_inputs())?;
     (c.generate_content())?;
     (c.check_input_timestamps())?;
     (c.adjust_stamp_timestamp())?;
+    (c.update_lock())?;
+    (c.record_maps())?;
     // (c.report_dir(Path::new(".")))?;
-    Ok(())
+    c.report_conflicts()
 }
 
-fn process_without_stamp(emit_rerun_if: bool) -> Result<()> {
+fn process_without_stamp(config: Config) -> Result<()> {
     println!("Running tango; no previously recorded run");
-    println!("\n\nemit rerun if: {:?}\n\n", emit_rerun_if);
-    let mut c = (Context::new(None))?;
-    c.emit_rerun_if = emit_rerun_if;
+    println!("\n\nemit rerun if: {:?}\n\n", config.rerun_if());
+    let mut c = (Context::new(None, config))?;
     (c.gather_inputs())?;
     (c.generate_content())?;
     (c.check_input_timestamps())?;
     (c.create_stamp())?;
     (c.adjust_stamp_timestamp())?;
+    (c.update_lock())?;
+    (c.record_maps())?;
     // (c.report_dir(Path::new(".")))?;
-    Ok(())
+    c.report_conflicts()
 }
 
+// A `.rs` file, and the other `.rs` files (if any) that blocks of its
+// `.md` twin are written to; see `pieces`.
 #[derive(Debug)]
-struct RsPath(PathBuf);
+struct RsPath(PathBuf, Vec<PathBuf>);
+// A `.md` file, and the chapters after it (if any) of the `.rs` file
+// assembled from it; see `chapters`.
 #[derive(Debug)]
-struct MdPath(PathBuf);
+struct MdPath(PathBuf, Vec<PathBuf>);
 
 
 struct Context {
+    config: Config,
     orig_stamp: Option<(File, mtime)>,
+    // `None` when running in `SyncMode::Mtime`.
+    orig_lock: Option<Lock>,
     src_inputs: Vec<Transform<RsPath, MdPath>>,
     lit_inputs: Vec<Transform<MdPath, RsPath>>,
+    // Targets whose content is already in sync with their source,
+    // but whose mtime should be brought up to the source's.
+    retimes: Vec<(PathBuf, mtime)>,
+    // Pairs that need a three-way merge.
+    merges: Vec<PendingMerge>,
+    // The `.rs` files of merged pairs left with conflict markers.
+    conflicted: Vec<PathBuf>,
+    // Every (rs, md) pair seen by gather_inputs, for update_lock.
+    pairs: Vec<(PathBuf, PathBuf)>,
     newest_stamp: Option<mtime>,
     emit_rerun_if: bool,
+    // When set, gather_inputs records every transform (including the
+    // ones it skips or refuses) in `plan` rather than failing.
+    planning: bool,
+    plan: Vec<PlannedTransform>,
+    // When set, only the pairs whose `.rs` path is listed here are
+    // gathered (and their lock entries updated).
+    only: Option<Vec<PathBuf>>,
 }
 
 trait Extensions {
@@ -344,56 +1290,214 @@ impl ops::Deref for MdPath {
     type Target = Path; fn deref(&self) -> &Path { &self.0 }
 }
 
-fn check_path(typename: &str, p: &Path, ext: &str, root: &str) {
-    println!("\n in check_path, the root is: {r:?} , path is: {p:?}, ext is {e:?}", r=root, p=p, e=ext);
-    if Extensions::extension(p) != Some(ext) { panic!("{t} requires `.{ext}` extension; path: {p:?}", t=typename, ext=ext, p=p); }
-    if !p.starts_with(root) { panic!("{t} must be rooted at `{root}/`; path: {p:?}", t=typename, root=root, p=p); }
+impl AsRef<Path> for RsPath {
+    fn as_ref(&self) -> &Path { &self.0 }
+}
+
+impl AsRef<Path> for MdPath {
+    fn as_ref(&self) -> &Path { &self.0 }
+}
+
+fn keep_file_name(p: &Path) -> std::result::Result<(), &'static str> {
+    match p.file_name().and_then(|x|x.to_str()) {
+        None =>
+            Err("file name is not valid unicode"),
+        Some(s) if s.starts_with('.') =>
+            Err("file name has leading period"),
+        Some(..) =>
+            Ok(()),
+    }
+}
+
+// The existing `.rs` and `.md` files that the configuration selects
+// for processing.
+fn candidates(config: &Config, report_skips: bool) -> Result<(Vec<RsPath>, Vec<MdPath>)> {
+    let mut rs_files: Vec<PathBuf> = Vec::new();
+    let mut md_files: Vec<PathBuf> = Vec::new();
+    for &(dir, is_rs) in &[(config.src_dir(), true), (config.lit_dir(), false)] {
+        // A literate directory named in the configuration need not
+        // exist until the first `.md` file is generated into it.
+        let dir = config.resolve(dir);
+        if !dir.exists() {
+            continue;
+        }
+        for ent in WalkDir::new(dir).into_iter() {
+            let ent = (ent)?;
+            let p = ent.path();
+            if let Err(why) = keep_file_name(p) {
+                if report_skips {
+                    println!("skipping {}; {}", p.display(), why);
+                }
+                continue;
+            }
+            if is_rs && p.rs_extension() {
+                rs_files.push(p.to_path_buf());
+            } else if !is_rs && p.md_extension() {
+                md_files.push(p.to_path_buf());
+            }
+        }
+    }
+    let chapters = config.chapters.iter().map(|(rs, mds)| (rs, &mds[0]));
+    for (rs, md) in config.mappings.iter().map(|(rs, md)| (rs, md)).chain(chapters) {
+        let (rs, md) = (config.resolve(rs), config.resolve(md));
+        if rs.exists() && !rs_files.contains(&rs) {
+            rs_files.push(rs);
+        }
+        if md.exists() && !md_files.contains(&md) {
+            md_files.push(md);
+        }
+    }
+
+    // A file is only taken if mapping it to its twin and back leads
+    // to the file itself; this is not so for e.g. `src/lib.md` when
+    // `src/lib.rs` is mapped to `README.md`.
+    let skip = |p: &Path, twin: &Path| {
+        if report_skips {
+            println!("skipping {}; {} is paired with another file", p.display(), twin.display());
+        }
+    };
+    let mut rs_paths: Vec<RsPath> = Vec::new();
+    for p in rs_files {
+        let rs = (RsPath::new(p, config))?;
+        let md = (rs.to_md(config))?;
+        if (md.to_rs(config))?.0 != rs.0 {
+            skip(&rs, &md);
+        } else if config.selects(&rs, &md) {
+            rs_paths.push(rs);
+        }
+    }
+    // The files written from `file=` blocks are synced along with the
+    // twin of the `.md` file they come from, not as pairs of their own.
+    let owners = (piece_owners(&rs_paths))?;
+    rs_paths.retain(|rs| match owners.get(&rs.0) {
+        Some(owner) => {
+            if report_skips {
+                println!("skipping {}; its code comes from the twin of {}", rs.display(), owner.display());
+            }
+            false
+        }
+        None => true,
+    });
+    let mut md_paths = Vec::new();
+    for p in md_files {
+        let md = (MdPath::new(p, config))?;
+        let rs = (md.to_rs(config))?;
+        if (rs.to_md(config))?.0 != md.0 || owners.contains_key(&rs.0) {
+            skip(&md, &rs);
+        } else if config.selects(&rs, &md) {
+            md_paths.push(md);
+        }
+    }
+    Ok((rs_paths, md_paths))
+}
+
+// Maps each file written from `file=` blocks to the `.rs` file whose
+// twin names it. A file can only take code from one `.md` file.
+fn piece_owners(rs_paths: &[RsPath]) -> Result<BTreeMap<PathBuf, PathBuf>> {
+    let mut owners = BTreeMap::new();
+    for rs in rs_paths {
+        for p in &rs.1 {
+            if *p == rs.0 {
+                return Err(Error::ConfigError(format!(
+                    "{}: a `file=` block names the file itself", rs.display())));
+            }
+            if let Some(other) = owners.insert(p.clone(), rs.0.clone()) {
+                return Err(Error::ConfigError(format!(
+                    "`file=` blocks for both {} and {} write to {}",
+                    other.display(), rs.display(), p.display())));
+            }
+        }
+    }
+    Ok(owners)
+}
+
+// A path outside its directory (say, a `.md` file among the sources)
+// has no twin; it is reported rather than paired with a made-up file.
+fn check_path(typename: &str, p: &Path, ext: &str, root: &Path) -> Result<()> {
+    if Extensions::extension(p) != Some(ext) {
+        return Err(Error::ConfigError(format!(
+            "{t} requires `.{ext}` extension; path: {p}", t=typename, ext=ext, p=p.display())));
+    }
+    if !p.starts_with(root) {
+        return Err(Error::ConfigError(format!(
+            "{t} must be rooted at `{root}/`; path: {p}", t=typename, root=root.display(), p=p.display())));
+    }
+    Ok(())
 }
 
 impl RsPath {
-    fn new(p: PathBuf) -> RsPath {
-        check_path("RsPath", &p, "rs", &get_src_dir());
-        RsPath(p)
+    fn new(p: PathBuf, config: &Config) -> Result<RsPath> {
+        if config.mapped_twin(&p).is_none() {
+            (check_path("RsPath", &p, "rs", &config.resolve(config.src_dir())))?;
+        }
+        let pieces = pieces_of(&p, config);
+        Ok(RsPath(p, pieces))
     }
-    fn to_md(&self) -> MdPath {
-        let mut p = PathBuf::new();
-        p.push(get_lit_dir());
-        for c in self.0.components().skip(1) {
-            let c: &OsStr = c.as_ref();
-            p.push(c.to_str().expect("how else can I replace root?"));
+    fn to_md(&self, config: &Config) -> Result<MdPath> {
+        if let Some(p) = config.mapped_twin(&self.0) {
+            return MdPath::new(p, config);
         }
-        p.set_extension("md");
-        MdPath::new(p)
+        let p = rebase(&self.0,
+                       &config.resolve(config.src_dir()),
+                       &config.resolve(config.lit_dir()),
+                       "md");
+        MdPath::new(p, config)
     }
 }
 
 impl MdPath {
-    fn new(p: PathBuf) -> MdPath {
-        check_path("MdPath", &p, "md", &get_lit_dir());
-        MdPath(p)
+    fn new(p: PathBuf, config: &Config) -> Result<MdPath> {
+        if config.mapped_twin(&p).is_none() {
+            (check_path("MdPath", &p, "md", &config.resolve(config.lit_dir())))?;
+        }
+        let chapters = config.later_chapters(&p);
+        Ok(MdPath(p, chapters))
     }
-    fn to_rs(&self) -> RsPath {
-        let mut p = PathBuf::new();
-        p.push(get_src_dir());
-        for c in self.0.components().skip(1) {
-            let c: &OsStr = c.as_ref();
-            p.push(c.to_str().expect("how else can I replace root?"));
+    fn to_rs(&self, config: &Config) -> Result<RsPath> {
+        if let Some(p) = config.mapped_twin(&self.0) {
+            return RsPath::new(p, config);
         }
-        p.set_extension("rs");
-        RsPath::new(p)
+        let p = rebase(&self.0,
+                       &config.resolve(config.lit_dir()),
+                       &config.resolve(config.src_dir()),
+                       "rs");
+        RsPath::new(p, config)
     }
 }
 
-trait Transforms: Sized + Mtime + fmt::Debug {
-    type Target: Mtime + fmt::Debug;
+// The other `.rs` files named by `file=` attributes in the meta notes
+// of `rs`, if it exists.
+fn pieces_of(rs: &Path, config: &Config) -> Vec<PathBuf> {
+    match fs::read_to_string(rs) {
+        // Targets that may not be written are not read, nor removed.
+        Ok(text) => pieces::targets(&text).into_iter().filter_map(|f| config.piece_path(&f)).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Maps `p`, which lies under the directory `from`, to the
+// corresponding path under `to`, with its extension replaced by
+// `ext`. Either directory may have any number of components, so
+// e.g. `crates/foo/src/a/b.rs` maps to `docs/lit/foo/a/b.md`.
+fn rebase(p: &Path, from: &Path, to: &Path, ext: &str) -> PathBuf {
+    let rest = p.strip_prefix(from).unwrap_or_else(|_| {
+        panic!("{:?} does not lie under {:?}", p, from)
+    });
+    let mut p = to.join(rest);
+    p.set_extension(ext);
+    p
+}
+
+trait Transforms: Sized + Mtime + Hashed + fmt::Debug + AsRef<Path> {
+    type Target: Mtime + Hashed + fmt::Debug + AsRef<Path>;
 
     // Computes path to desired target based on self's (source) path.
-    fn target(&self) -> Self::Target;
+    fn target(&self, config: &Config) -> Result<Self::Target>;
 
     // Constructs a transform for generating the target from self
     // (which is a path to the source), gathering the current
     // timestamps on both the source and the target.
-    fn transform(self) -> Result<Transform<Self, Self::Target>> {
+    fn transform(self, config: &Config) -> Result<Transform<Self, Self::Target>> {
         let source_time = match self.modified() {
             Ok(MtimeResult::Modified(t)) => t,
             Ok(MtimeResult::NonExistant) => panic!("impossible for {:?} to be NonExistant", self),
@@ -403,7 +1507,7 @@ trait Transforms: Sized + Mtime + fmt::Debug {
             }
         };
 
-        let target = self.target();
+        let target = (self.target(config))?;
         let target_time = match target.modified() {
             Ok(t) => t,
             Err(e) => {
@@ -411,8 +1515,15 @@ trait Transforms: Sized + Mtime + fmt::Debug {
                 return Err(e);
             }
         };
+        let source_hash = (self.content_hash())?;
+        let target_hash = match target_time {
+            MtimeResult::Modified(_) => Some((target.content_hash())?),
+            MtimeResult::NonExistant => None,
+        };
         Ok(Transform { source_time: source_time,
                        target_time: target_time,
+                       source_hash,
+                       target_hash,
                        original: self,
                        generate: target,
         })
@@ -421,22 +1532,34 @@ trait Transforms: Sized + Mtime + fmt::Debug {
 
 impl Transforms for RsPath {
     type Target = MdPath;
-    fn target(&self) -> MdPath { self.to_md() }
+    fn target(&self, config: &Config) -> Result<MdPath> { self.to_md(config) }
 }
 
 impl Transforms for MdPath {
     type Target = RsPath;
-    fn target(&self) -> RsPath { self.to_rs() }
+    fn target(&self, config: &Config) -> Result<RsPath> { self.to_rs(config) }
 }
 
 #[derive(Debug)]
 pub struct Transform<X, Y> {
     source_time: mtime,
     target_time: MtimeResult,
+    source_hash: ContentHash,
+    target_hash: Option<ContentHash>,
     original: X,
     generate: Y,
 }
 
+impl<X, Y> Transform<X, Y> {
+    // The hash and timestamp of a target that is known to exist.
+    fn target_state(&self) -> (ContentHash, mtime) {
+        match (self.target_hash, self.target_time) {
+            (Some(hash), MtimeResult::Modified(time)) => (hash, time),
+            _ => panic!("target of transform does not exist"),
+        }
+    }
+}
+
 pub mod check {
     use std::error::Error as ErrorTrait;
     use std::fmt;
@@ -450,9 +1573,10 @@ pub mod check {
         TargetYoungerThanOriginal { tgt: String, src: String },
         NoTangoStampExists { tgt: String, src: String },
         TangoStampOlderThanTarget { tgt: String },
+        BothModifiedSinceLock { src: String, tgt: String },
     }
     #[derive(Debug)]
-    pub struct Error(ErrorKind, PathTransform);
+    pub struct Error(ErrorKind, Box<PathTransform>);
 
     impl fmt::Display for Error {
         fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
@@ -471,6 +1595,11 @@ pub mod check {
                                therefore we assume source and target have diverged since last tango run.",
                            tgt)
                 }
+                ErrorKind::BothModifiedSinceLock { ref src, ref tgt } => {
+                    write!(w, "both source `{}` and target `{}` have changed since the contents \
+                               recorded in `tango.lock`; refusing to overwrite either one.",
+                           src, tgt)
+                }
             }
         }
     }
@@ -489,6 +1618,9 @@ pub mod check {
                     "`tango.stamp` is older than target; \
                      therefore we assume source and target have diverged since last tango run."
                 }
+                ErrorKind::BothModifiedSinceLock { .. } => {
+                    "both source and target have changed since the contents recorded in `tango.lock`"
+                }
             }
         }
     }
@@ -503,16 +1635,27 @@ pub mod check {
                                 generate: self.generate.to_path_buf(),
                                 source_time: self.source_time,
                                 target_time: self.target_time,
+                                source_hash: self.source_hash,
+                                target_hash: self.target_hash,
             };
-            Error(kind, t)
+            Error(kind, Box::new(t))
         }
     }
 }
 
-enum TransformNeed { Needed, Unneeded, }
+enum TransformNeed { Needed, Unneeded, Retime, Merge, }
+
+// A pair whose `.rs` and `.md` were both edited since the last sync,
+// along with the hashes they had when we gathered them.
+struct PendingMerge {
+    rs: PathBuf,
+    rs_state: (ContentHash, mtime),
+    md: PathBuf,
+    md_state: (ContentHash, mtime),
+}
 
 impl Context {
-    fn new(opt_stamp: Option<File>) -> Result<Context> {
+    fn new(opt_stamp: Option<File>, config: Config) -> Result<Context> {
         let stamp_modified = match opt_stamp {
             None => None,
             Some(stamp) => {
@@ -524,17 +1667,32 @@ impl Context {
                 Some((stamp, mtime))
             }
         };
+        let lock = match config.sync_mode() {
+            SyncMode::Mtime => None,
+            SyncMode::ContentHash if config.resolve(LOCK).exists() =>
+                Some((Lock::read(config.resolve(LOCK)))?),
+            SyncMode::ContentHash => Some(Lock::new()),
+        };
         let c = Context {
+            emit_rerun_if: config.rerun_if(),
+            config,
             orig_stamp: stamp_modified,
+            orig_lock: lock,
             src_inputs: Vec::new(),
             lit_inputs: Vec::new(),
+            retimes: Vec::new(),
+            merges: Vec::new(),
+            conflicted: Vec::new(),
+            pairs: Vec::new(),
             newest_stamp: None,
-            emit_rerun_if: true,
+            planning: false,
+            plan: Vec::new(),
+            only: None,
         };
         Ok(c)
     }
 
-    fn check_transform<X, Y>(&self, t: &Transform<X, Y>) -> check::Result<TransformNeed>
+    fn check_transform<X, Y>(&self, t: &Transform<X, Y>) -> check::Result<(TransformNeed, Reason)>
         where X: ops::Deref<Target=Path> + Mtime,
               Y: ops::Deref<Target=Path> + Mtime,
     {
@@ -546,19 +1704,23 @@ impl Context {
             MtimeResult::Modified(t) => t,
             MtimeResult::NonExistant => {
                 assert!(!t.generate.exists());
-                return Ok(TransformNeed::Needed);
+                return Ok((TransformNeed::Needed, Reason::TargetMissing));
             }
         };
         // let src = t.original.display().to_string();
         // let tgt = t.generate.display().to_string();
         let s_mod = t.source_time;
 
+        if let Some(need) = self.check_transform_content(t, t_mod)? {
+            return Ok(need);
+        }
+
         let same_age_at_low_precision = s_mod.to_ms() == t_mod.to_ms();
 
         if t_mod > s_mod {
             // Target is newer than source: therefore we do not want to
             // overwrite the target via this transform.
-            return Ok(TransformNeed::Unneeded);
+            return Ok((TransformNeed::Unneeded, Reason::TargetNewer));
         }
 
         // Now know:  t_mod <= s_mod
@@ -574,7 +1736,7 @@ impl Context {
                           target: {TGT:?} timestamp: {TGT_TS}\n",
                      SRC=t.original.display(), SRC_TS=s_mod.date_fulltime_badly(),
                      TGT=t.generate.display(), TGT_TS=t_mod.date_fulltime_badly());
-            return Ok(TransformNeed::Unneeded);
+            return Ok((TransformNeed::Unneeded, Reason::SameTimestamp));
         }
 
         // Now know: t_mod is older than source even after truncating
@@ -622,15 +1784,58 @@ impl Context {
         // s_mod >= t_mod (and t_mod <= stamp_time if stamp exists).
         //
         // Thus it is safe to overwrite `t` based on source content.
-        Ok(TransformNeed::Needed)
+        Ok((TransformNeed::Needed, Reason::SourceNewer))
+    }
+
+    // Decides the transform from the hashes recorded in `tango.lock`.
+    // Returns `None` when there is no recorded state for this pair,
+    // in which case the caller falls back to comparing timestamps.
+    fn check_transform_content<X, Y>(&self, t: &Transform<X, Y>, t_mod: mtime)
+                                     -> check::Result<Option<(TransformNeed, Reason)>>
+        where X: ops::Deref<Target=Path>,
+              Y: ops::Deref<Target=Path>,
+    {
+        let lock = match self.orig_lock {
+            Some(ref lock) => lock,
+            None => return Ok(None),
+        };
+        let source = self.config.relative(&t.original);
+        let target = self.config.relative(&t.generate);
+        let (src_hash, tgt_hash) = match lock.lookup(source, target) {
+            Some(hashes) => hashes,
+            None => return Ok(None),
+        };
+        let src_changed = t.source_hash != src_hash;
+        let tgt_changed = t.target_hash != Some(tgt_hash);
+        match (src_changed, tgt_changed) {
+            (true, true) => {
+                // We can only merge if the bases we saved at the last
+                // sync, of the `.rs` file and its pieces, are the ones
+                // `tango.lock` describes.
+                let entry = lock.find(source, target).expect("lookup succeeded");
+                let bases: Vec<PathBuf> = entry.pieces.iter().map(|p| self.config.base_path(p)).collect();
+                match group_hash(&self.config.base_path(&entry.rs_path), &bases) {
+                    Ok(h) if h == entry.rs_hash =>
+                        Ok(Some((TransformNeed::Merge, Reason::BothChangedSinceLock))),
+                    _ => Err(t.error(check::ErrorKind::BothModifiedSinceLock {
+                        src: t.original.display().to_string(),
+                        tgt: t.generate.display().to_string(),
+                    })),
+                }
+            }
+            (true, false) => Ok(Some((TransformNeed::Needed, Reason::SourceChangedSinceLock))),
+            // The transform in the other direction handles this case.
+            (false, true) => Ok(Some((TransformNeed::Unneeded, Reason::TargetChangedSinceLock))),
+            (false, false) if t.source_time.to_ms() > t_mod.to_ms() =>
+                Ok(Some((TransformNeed::Retime, Reason::StaleTimestamp))),
+            (false, false) => Ok(Some((TransformNeed::Unneeded, Reason::UnchangedSinceLock))),
+        }
     }
 
     #[cfg(not_now)]
     fn report_dir(&self, p: &Path) -> Result<()> {
-        let src_dir = get_src_dir();
-        let lit_dir = get_lit_dir();
-        let src_path = Path::new(&src_dir);
-        let lit_path = Path::new(&lit_dir);
+        let src_path = self.config.resolve(self.config.src_dir());
+        let lit_path = self.config.resolve(self.config.lit_dir());
 
         for (i, ent) in (WalkDir::new(p))?.enumerate() {
             let ent = (ent)?;
@@ -658,24 +1863,65 @@ impl Context {
         self.update_newest_time(t.source_time);
         self.lit_inputs.push(t);
     }
+    fn push_retime<X, Y: ops::Deref<Target=Path>>(&mut self, t: Transform<X, Y>) {
+        self.update_newest_time(t.source_time);
+        self.retimes.push((t.generate.to_path_buf(), t.source_time));
+    }
+    fn record_plan<X, Y>(&mut self, direction: Direction, t: &Transform<X, Y>,
+                         outcome: &check::Result<(TransformNeed, Reason)>)
+        where X: ops::Deref<Target=Path>, Y: ops::Deref<Target=Path>
+    {
+        if !self.planning {
+            return;
+        }
+        let (action, reason) = match *outcome {
+            Ok((TransformNeed::Needed, ref r)) => (Action::Generate, r.clone()),
+            Ok((TransformNeed::Unneeded, ref r)) => (Action::Skip, r.clone()),
+            Ok((TransformNeed::Retime, ref r)) => (Action::Retime, r.clone()),
+            Ok((TransformNeed::Merge, ref r)) => (Action::Merge, r.clone()),
+            Err(ref e) => (Action::Refuse, Reason::Refused(e.to_string())),
+        };
+        self.plan.push(PlannedTransform {
+            direction,
+            source: t.original.to_path_buf(),
+            target: t.generate.to_path_buf(),
+            source_time: t.source_time,
+            target_time: match t.target_time {
+                MtimeResult::Modified(time) => Some(time),
+                MtimeResult::NonExistant => None,
+            },
+            action,
+            reason,
+        });
+    }
+    fn push_merge(&mut self, rs: (&Path, ContentHash, mtime), md: (&Path, ContentHash, mtime)) {
+        // Both directions of the pair report the merge; keep one.
+        if !self.merges.iter().any(|m| m.rs == rs.0) {
+            self.update_newest_time(rs.2);
+            self.update_newest_time(md.2);
+            self.merges.push(PendingMerge {
+                rs: rs.0.to_path_buf(),
+                rs_state: (rs.1, rs.2),
+                md: md.0.to_path_buf(),
+                md_state: (md.1, md.2),
+            });
+        }
+    }
+    fn wants(&self, rs: &Path) -> bool {
+        match self.only {
+            None => true,
+            Some(ref only) => only.iter().any(|p| p == rs),
+        }
+    }
+    fn push_pair(&mut self, rs: &Path, md: &Path) {
+        if !self.pairs.iter().any(|(r, _)| r == rs) {
+            self.pairs.push((rs.to_path_buf(), md.to_path_buf()));
+        }
+    }
 
     fn gather_inputs(&mut self) -> Result<()> {
         // println!("gather_inputs");
-        let src_dir = get_src_dir();
-        let lit_dir = get_lit_dir();
-        let src_path = Path::new(&src_dir);
-        let lit_path = Path::new(&lit_dir);
-
-        fn keep_file_name(p: &Path) -> std::result::Result<(), &'static str> {
-            match p.file_name().and_then(|x|x.to_str()) {
-                None =>
-                    Err("file name is not valid unicode"),
-                Some(s) if s.starts_with('.') =>
-                    Err("file name has leading period"),
-                Some(..) =>
-                    Ok(()),
-            }
-        }
+        let (rs_files, md_files) = (candidates(&self.config, true))?;
 
         fn warn_if_nonexistant<M:Mtime+fmt::Debug>(m: &M) -> Result<()> {
             match m.modified() {
@@ -703,28 +1949,32 @@ impl Context {
         // corresponding target .md files.
 
         // println!("gather-rs");
-        for ent in WalkDir::new(src_path).into_iter() {
-            let ent = (ent)?;
-            let p = ent.path();
-            if let Err(why) = keep_file_name(p) {
-                println!("skipping {}; {}", p.display(), why);
-                continue;
-            }
-            if !p.rs_extension() {
-                // println!("gather-rs skip {} due to non .rs", p.display());
+        for rs in rs_files {
+            if !self.wants(&rs) {
                 continue;
             }
-            let rs = RsPath::new(p.to_path_buf());
             (warn_if_nonexistant(&rs))?;
 
             if self.emit_rerun_if {
                 println!("cargo:rerun-if-changed={}", &rs.display());
             }
 
-            let t = (rs.transform())?;
-            match self.check_transform(&t) {
-                Ok(TransformNeed::Needed) => self.push_src(t),
-                Ok(TransformNeed::Unneeded) => {}
+            let t = (rs.transform(&self.config))?;
+            let outcome = self.check_transform(&t);
+            self.record_plan(Direction::RsToMd, &t, &outcome);
+            self.push_pair(&t.original, &t.generate);
+            match outcome {
+                Ok((TransformNeed::Needed, _)) => self.push_src(t),
+                Ok((TransformNeed::Unneeded, _)) => {}
+                Ok((TransformNeed::Retime, _)) => self.push_retime(t),
+                Ok((TransformNeed::Merge, _)) => {
+                    let (md_hash, md_time) = t.target_state();
+                    self.push_merge((&t.original, t.source_hash, t.source_time),
+                                    (&t.generate, md_hash, md_time))
+                }
+                // When planning, the error has been recorded in the
+                // plan and we carry on to report the remaining pairs.
+                Err(_) if self.planning => {}
                 Err(e) => {
                     println!("gather_inputs err: {}", e);
                     return Err(Error::CheckInputError {
@@ -738,35 +1988,37 @@ impl Context {
         // exist, and schedules transforms that would turn them into
         // corresponding target .rs files.
 
-        //println!("gather-md, lit_path is: {:?}", lit_path);
-        for ent in WalkDir::new(lit_path).into_iter() {
-            //println!("ent is {:?}", ent);
-            let ent = (ent)?;
-            let p = ent.path();
-            if let Err(why) = keep_file_name(p) {
-                println!("skipping {}; {}", p.display(), why);
+        for md in md_files {
+            if !self.wants(&(md.to_rs(&self.config))?) {
                 continue;
             }
-            if !p.md_extension() {
-                // println!("gather-md skip {} due to non .md", p.display());
-                continue;
-            }
-            let md = MdPath::new(p.to_path_buf());
             (warn_if_nonexistant(&md))?;
 
             if self.emit_rerun_if {
                 println!("cargo:rerun-if-changed={}", &md.display());
             }
 
-            let t = (md.transform())?;
-            match self.check_transform(&t) {
-                Ok(TransformNeed::Needed) => {
+            let t = (md.transform(&self.config))?;
+            let outcome = self.check_transform(&t);
+            self.record_plan(Direction::MdToRs, &t, &outcome);
+            self.push_pair(&t.generate, &t.original);
+            match outcome {
+                Ok((TransformNeed::Needed, _)) => {
                     // println!("gather-md add {:?}", t);;
                     self.push_lit(t)
                 }
-                Ok(TransformNeed::Unneeded) => {
+                Ok((TransformNeed::Unneeded, _)) => {
                     // println!("gather-md discard unneeded {:?}", t);;
                 }
+                Ok((TransformNeed::Retime, _)) => self.push_retime(t),
+                Ok((TransformNeed::Merge, _)) => {
+                    let (rs_hash, rs_time) = t.target_state();
+                    self.push_merge((&t.generate, rs_hash, rs_time),
+                                    (&t.original, t.source_hash, t.source_time))
+                }
+                // When planning, the error has been recorded in the
+                // plan and we carry on to report the remaining pairs.
+                Err(_) if self.planning => {}
                 Err(e) => {
                     println!("gather_inputs err: {}", e);
                     return Err(Error::CheckInputError {
@@ -786,28 +2038,44 @@ impl Context {
     }
     fn generate_content(&mut self) -> Result<()> {
         for &Transform { ref original, ref generate, source_time, .. } in &self.src_inputs {
-            let source = (File::open(&original.0))?;
-            let target = (File::create(&generate.0))?;
             assert!(source_time > 0);
             println!("generating lit {:?}", &generate.0);
-            (rs2md(source, target))?;
             let timestamp = source_time.to_filetime();
-            println!("backdating lit {:?} to {}", &generate.0, source_time.date_fulltime_badly());
-            (set_file_times(&generate.0, timestamp, timestamp))?;
+            for (p, content) in (rs2md_group(&self.config, original, generate))? {
+                (create_parent_dir(&p))?;
+                (write_file(&p, &content))?;
+                println!("backdating lit {:?} to {}", p, source_time.date_fulltime_badly());
+                (set_file_times(&p, timestamp, timestamp))?;
+            }
         }
         for &mut Transform { ref original, ref generate, ref mut source_time, .. } in &mut self.lit_inputs {
-            let source = (File::open(&original.0))?;
-            let target = (File::create(&generate.0))?;
             assert!(*source_time > 0);
             println!("generating src {:?}", &generate.0);
-            (md2rs(source, target))?;
+            let (content, pieces) = (md2rs_group(&self.config, original, generate))?;
+            (create_parent_dir(&generate.0))?;
+            (write_file(&generate.0, &content))?;
             println!("backdating src {:?} to {}", &generate.0, source_time.date_fulltime_badly());
             (set_file_times(&generate.0,
                                 source_time.to_filetime(),
                                 source_time.to_filetime()))?;
-            let source = (File::open(&original.0))?;
+            for (p, content) in &pieces {
+                println!("generating src {:?} from {:?}", p, original.0);
+                (create_parent_dir(p))?;
+                (write_file(p, content))?;
+                (set_file_times(p, source_time.to_filetime(), source_time.to_filetime()))?;
+            }
+            // A file that no block writes to any more would otherwise
+            // be taken up as a pair of its own on the next run. (Only
+            // files that blocks may write to are listed; see
+            // `Config::piece_path`.)
+            for p in &generate.1 {
+                if p.exists() && !pieces.iter().any(|(q, _)| q == p) {
+                    println!("removing {:?}; no block of {:?} writes to it", p, original.0);
+                    (fs::remove_file(p))?;
+                }
+            }
             let target = (File::open(&generate.0))?;
-            match (source.modified(), target.modified()) {
+            match (original.modified(), target.modified()) {
                 (Ok(MtimeResult::Modified(src_time)),
                  Ok(MtimeResult::Modified(tgt_time))) => {
                     // At this point, we would *like* to assert this:
@@ -824,6 +2092,16 @@ impl Context {
                 (_, Err(_)) => panic!("errored looking up target time"),
             }
         }
+        for &(ref generate, source_time) in &self.retimes {
+            println!("content of {:?} already matches its source; backdating to {}",
+                     generate, source_time.date_fulltime_badly());
+            (set_file_times(generate, source_time.to_filetime(), source_time.to_filetime()))?;
+        }
+        for m in &self.merges {
+            if (merge_pair(m, &self.config))? > 0 {
+                self.conflicted.push(m.rs.clone());
+            }
+        }
         Ok(())
     }
     fn check_input_timestamps(&mut self) -> Result<()> {
@@ -852,7 +2130,7 @@ impl Context {
         Ok(())
     }
     fn create_stamp(&mut self) -> Result<()> {
-        let _f = (File::create(STAMP))?;
+        let _f = (File::create(self.config.resolve(STAMP)))?;
         Ok(())
     }
     fn adjust_stamp_timestamp(&mut self) -> Result<()> {
@@ -860,7 +2138,7 @@ impl Context {
             assert!(stamp > 0);
             println!("re-stamping tango.stamp to {}", stamp.date_fulltime_badly());
 
-            match set_file_times(STAMP, stamp.to_filetime(), stamp.to_filetime()) {
+            match set_file_times(self.config.resolve(STAMP), stamp.to_filetime(), stamp.to_filetime()) {
                 Ok(()) => Ok(()),
                 Err(e) => Err(Error::IoError(e)),
             }
@@ -868,27 +2146,473 @@ impl Context {
             Ok(())
         }
     }
+    fn update_lock(&mut self) -> Result<()> {
+        let mut lock = match (self.orig_lock.as_ref(), self.only.as_ref()) {
+            (None, _) => return Ok(()),
+            (Some(_), None) => Lock::new(),
+            // Entries for pairs outside the restriction are kept as is.
+            (Some(orig), Some(only)) => {
+                let mut lock = orig.clone();
+                for rs in only {
+                    lock.remove(self.config.relative(rs));
+                }
+                lock
+            }
+        };
+        for (rs, md) in &self.pairs {
+            if !rs.exists() || !md.exists() {
+                continue;
+            }
+            // A pair left with conflict markers is not in sync, and
+            // its bases are kept for the merge that resolves them.
+            if self.conflicted.contains(rs) {
+                continue;
+            }
+            // The `.rs` file's pieces are read afresh, as generating it
+            // may have changed them.
+            let rs = (RsPath::new(rs.clone(), &self.config))?;
+            let md = (MdPath::new(md.clone(), &self.config))?;
+            for p in Some(&rs.0).into_iter().chain(&rs.1) {
+                (save_base(p, &self.config.base_path(p)))?;
+            }
+            lock.record(lock::Entry {
+                rs_path: self.config.relative(&rs).to_path_buf(),
+                rs_hash: (rs.content_hash())?,
+                md_path: self.config.relative(&md).to_path_buf(),
+                md_hash: (md.content_hash())?,
+                pieces: rs.1.iter().map(|p| self.config.relative(p).to_path_buf()).collect(),
+            });
+        }
+        if self.orig_lock.as_ref() != Some(&lock) {
+            (lock.write(self.config.resolve(LOCK)))?;
+        }
+        Ok(())
+    }
+
+    fn report_conflicts(&self) -> Result<()> {
+        if self.conflicted.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::MergeConflicts(self.conflicted.clone()))
+        }
+    }
+
+    fn record_maps(&mut self) -> Result<()> {
+        for (rs, md) in &self.pairs {
+            if !rs.exists() || !md.exists() {
+                continue;
+            }
+            let rs = (RsPath::new(rs.clone(), &self.config))?;
+            let md = (MdPath::new(md.clone(), &self.config))?;
+            let md_hash = (md.content_hash())?;
+            let current = Some(&rs.0).into_iter().chain(&rs.1).all(|p| {
+                SourceMap::read(self.config.map_path(p)).ok().map(|m| m.md_hash()) == Some(md_hash)
+            });
+            if current {
+                continue;
+            }
+            // The maps are only an aid; a `.md` file that does not
+            // convert cleanly (say, with a stale playground link) is
+            // left without one rather than failing the sync.
+            let maps = match md2rs_mapped(&self.config, &md, &rs) {
+                Ok((_, _, maps)) => maps,
+                Err(_) => continue,
+            };
+            for (p, map) in maps {
+                let map_path = self.config.map_path(&p);
+                (create_parent_dir(&map_path))?;
+                (map.write(&map_path))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Merges a pair whose sides were both edited since the last sync.
+// The `.md` is converted to its `.rs` form, and each of the resulting
+// files (the `.rs` file and its pieces) is merged against its saved
+// base and its current content; the results are written back, and
+// carried back into the `.md`. Conflicting regions are delimited by
+// conflict markers, which are ordinary code lines as far as the
+// converters are concerned, and thus survive being carried back into
+// the `.md`. Returns the number of conflicts.
+fn merge_pair(m: &PendingMerge, config: &Config) -> Result<usize> {
+    let md = (MdPath::new(m.md.clone(), config))?;
+    let rs = (RsPath::new(m.rs.clone(), config))?;
+    for &(path, hash, (old_hash, time)) in &[(&m.md, (md.content_hash())?, m.md_state),
+                                              (&m.rs, (rs.content_hash())?, m.rs_state)] {
+        if hash != old_hash {
+            return Err(Error::ConcurrentUpdate {
+                path_buf: path.clone(),
+                old_time: time,
+                new_time: (path.metadata())?.timestamp(),
+            });
+        }
+    }
+
+    let (main, pieces) = (md2rs_group(config, &md, &m.rs))?;
+    let mut ours = vec![(m.rs.clone(), main)];
+    ours.extend(pieces);
+    // A piece only one side has is empty on the other.
+    for p in &rs.1 {
+        if !ours.iter().any(|(q, _)| q == p) {
+            ours.push((p.clone(), String::new()));
+        }
+    }
+
+    println!("merging {:?} and {:?}; both changed since the last sync", m.md, m.rs);
+    let read_or_empty = |p: &Path| if p.exists() { read_file(p) } else { Ok(String::new()) };
+    let mut conflicts = 0;
+    for (p, ours) in &ours {
+        let base = (read_or_empty(&config.base_path(p)))?;
+        let theirs = (read_or_empty(p))?;
+        let merged = merge::merge3(&base, ours, &theirs,
+                                   &m.md.display().to_string(),
+                                   &p.display().to_string());
+        conflicts += merged.conflicts;
+        if p.exists() || !merged.text.is_empty() {
+            (create_parent_dir(p))?;
+            (write_file(p, &merged.text))?;
+        }
+    }
+    if conflicts > 0 {
+        println!("warning: {} conflict(s) merging {:?} and {:?}; \
+                  resolve the conflict markers in either file and rerun tango",
+                 conflicts, m.md, m.rs);
+    }
+
+    // Give every file the same timestamp so the mtime fallback sees
+    // them as in sync. The merged `.rs` file may name other pieces.
+    let rs = (RsPath::new(m.rs.clone(), config))?;
+    let rs_time = (m.rs.metadata())?.timestamp();
+    for p in &rs.1 {
+        if p.exists() {
+            (set_file_times(p, rs_time.to_filetime(), rs_time.to_filetime()))?;
+        }
+    }
+    for (p, content) in (rs2md_group(config, &rs, &md))? {
+        (write_file(&p, &content))?;
+        (set_file_times(&p, rs_time.to_filetime(), rs_time.to_filetime()))?;
+    }
+    Ok(conflicts)
+}
+
+// Saves `p` as its base at `base`, unless it is saved already; a base
+// is removed along with its file.
+fn save_base(p: &Path, base: &Path) -> Result<()> {
+    if !p.exists() {
+        if base.exists() {
+            (fs::remove_file(base))?;
+        }
+        return Ok(());
+    }
+    let content = (read_file(p))?;
+    if ContentHash::of_file(base).ok() != Some(ContentHash::of_bytes(content.as_bytes())) {
+        (create_parent_dir(base))?;
+        (write_file(base, &content))?;
+    }
+    Ok(())
 }
 
-fn rs2md<R:Read, W:Write>(source: R, target: W) -> Result<()> {
-    let mut converter = rs2md::Converter::new();
-    converter.convert(source, target).map_err(Error::IoError)
+fn read_file(p: &Path) -> Result<String> {
+    let mut s = String::new();
+    ((File::open(p))?.read_to_string(&mut s))?;
+    Ok(s)
 }
 
-fn md2rs<R:Read, W:Write>(source: R, target: W) -> Result<()> {
-    let converter = md2rs::Converter::new();
-    converter.convert(source, target).map_err(From::from)
+// Generated files may belong to module directories that do not yet
+// exist on the target side.
+fn create_parent_dir(p: &Path) -> Result<()> {
+    match p.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir).map_err(Error::IoError),
+        _ => Ok(()),
+    }
+}
+
+fn write_file(p: &Path, content: &str) -> Result<()> {
+    let mut f = (File::create(p))?;
+    (f.write_all(content.as_bytes()))?;
+    (f.flush())?;
+    Ok(())
+}
+
+/// Converts a single `.rs` file read from `source` into Markdown.
+pub fn rs2md<R:Read, W:Write>(source: R, target: W) -> Result<()> {
+    rs2md_with_config(&Config::new(), source, target)
+}
+
+/// Converts a single `.md` file read from `source` into Rust.
+pub fn md2rs<R:Read, W:Write>(source: R, target: W) -> Result<()> {
+    md2rs_with_config(&Config::new(), source, target)
+}
+
+/// Like `rs2md`, but using the fence and playground settings of
+/// `config` (as given; configuration files are not consulted).
+///
+/// As with `md2rs_with_config`, the whole output is written even if
+/// the input draws warnings, but the conversion then fails with
+/// `Error::Warnings`; `rs_to_md_with_config` returns them instead.
+pub fn rs2md_with_config<R:Read, W:Write>(config: &Config, source: R, target: W) -> Result<()> {
+    let warnings = (rs_to_md_stream(config, io::BufReader::new(source), target))?;
+    if warnings.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Warnings(warnings))
+    }
+}
+
+/// Like `md2rs`, but using the fence and playground settings of
+/// `config` (as given; configuration files are not consulted).
+///
+/// As with `rs2md_with_config`, the whole output is written even if
+/// the input draws warnings, but the conversion then fails with
+/// `Error::Warnings`; `md_to_rs_with_config` returns them instead.
+pub fn md2rs_with_config<R:Read, W:Write>(config: &Config, source: R, target: W) -> Result<()> {
+    let warnings = (md_to_rs_stream(config, io::BufReader::new(source), target))?;
+    if warnings.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Warnings(warnings))
+    }
+}
+
+/// The output of a conversion in memory, along with any warnings
+/// about its input.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Conversion {
+    pub output: String,
+    pub warnings: Vec<Warning>,
+}
+
+/// Converts the Markdown `md` into Rust, with the default settings.
+/// Fails only if its code chunks are ill-formed.
+pub fn md_to_rs(md: &str) -> Result<Conversion> {
+    md_to_rs_with_config(&Config::new(), md)
+}
+
+/// Converts the Rust `rs` into Markdown, with the default settings.
+pub fn rs_to_md(rs: &str) -> Result<Conversion> {
+    rs_to_md_with_config(&Config::new(), rs)
+}
+
+/// Like `md_to_rs`, but using the fence and playground settings of
+/// `config` (as given; configuration files are not consulted).
+pub fn md_to_rs_with_config(config: &Config, md: &str) -> Result<Conversion> {
+    let mut output = Vec::new();
+    let warnings = (md_to_rs_stream(config, md.as_bytes(), &mut output))?;
+    Ok(Conversion { output: String::from_utf8_lossy(&output).into_owned(), warnings })
+}
+
+/// Like `rs_to_md`, but using the fence and playground settings of
+/// `config` (as given; configuration files are not consulted).
+pub fn rs_to_md_with_config(config: &Config, rs: &str) -> Result<Conversion> {
+    let mut output = Vec::new();
+    let warnings = (rs_to_md_stream(config, rs.as_bytes(), &mut output))?;
+    Ok(Conversion { output: String::from_utf8_lossy(&output).into_owned(), warnings })
+}
+
+/// Converts the Markdown read from `source` into Rust written to
+/// `target`, returning any warnings about the input.
+pub fn md_to_rs_stream<R:BufRead, W:Write>(config: &Config, source: R, mut target: W) -> Result<Vec<Warning>> {
+    let mut converter = MdToRs::new(config);
+    (converter.convert_reader(source, &mut target))?;
+    Ok(converter.take_warnings())
+}
+
+/// Converts the Rust read from `source` into Markdown written to
+/// `target`, returning any warnings about the input.
+pub fn rs_to_md_stream<R:BufRead, W:Write>(config: &Config, source: R, mut target: W) -> Result<Vec<Warning>> {
+    let mut converter = RsToMd::new(config);
+    (converter.convert_reader(source, &mut target))?;
+    Ok(converter.take_warnings())
+}
+
+/// Brings the Markdown `md` into tango's normal form, with the default
+/// settings; see `normalize_md_with_config`.
+pub fn normalize_md(md: &str) -> Result<Conversion> {
+    normalize_md_with_config(&Config::new(), md)
+}
+
+/// Brings the Rust `rs` into tango's normal form, with the default
+/// settings; see `normalize_rs_with_config`.
+pub fn normalize_rs(rs: &str) -> Result<Conversion> {
+    normalize_rs_with_config(&Config::new(), rs)
+}
+
+/// Brings the Markdown `md` into the normal form in which it converts
+/// to Rust and back unchanged, by doing just that. The normal form `n`
+/// converts to the same Rust as `md` does, and `n` back again.
+///
+/// The conversion drops what the `.rs` file has no way to carry: in
+/// the normal form, Rust code blocks are fenced with backticks (no
+/// more than their code calls for; tildes, if the info string holds
+/// a backtick), and with the first of the fence
+/// languages; blank lines at the ends of a block are outside it,
+/// unless it has a meta note or follows another block directly; code
+/// lines holding only whitespace are empty; no blank lines end the
+/// text; and playground links are up to date.
+///
+/// That holds as long as there are no warnings: a line of code that
+/// starts with `//@`, for one, would be read back as Markdown.
+pub fn normalize_md_with_config(config: &Config, md: &str) -> Result<Conversion> {
+    let rs = (md_to_rs_with_config(config, md))?;
+    let normal = (rs_to_md_with_config(config, &rs.output))?;
+    Ok(Conversion { output: normal.output, warnings: rs.warnings.into_iter().chain(normal.warnings).collect() })
+}
+
+/// Brings the Rust `rs` into the normal form in which it converts to
+/// Markdown and back unchanged: the Rust that the Markdown it converts
+/// to converts to. As for `normalize_md_with_config`, that holds as
+/// long as there are no warnings.
+pub fn normalize_rs_with_config(config: &Config, rs: &str) -> Result<Conversion> {
+    let md = (rs_to_md_with_config(config, rs))?;
+    let normal = (md_to_rs_with_config(config, &md.output))?;
+    Ok(Conversion { output: normal.output, warnings: md.warnings.into_iter().chain(normal.warnings).collect() })
+}
+
+// Converts `rs` into Markdown, filling in the blocks written to its
+// pieces from those files. Returns the content of `md`, or of each
+// of its chapters.
+fn rs2md_group(config: &Config, rs: &RsPath, md: &MdPath) -> Result<Vec<(PathBuf, String)>> {
+    let mut converter = rs2md::Converter::new(config);
+    for p in &rs.1 {
+        if p.exists() {
+            converter.add_pieces(p.clone(), &(read_file(p))?);
+        }
+    }
+    let source = (read_file(&rs.0))?;
+    if md.1.is_empty() {
+        let mut out = Vec::new();
+        (converter.convert_reader(source.as_bytes(), &mut out))?;
+        print_warnings(&mut converter, &rs.0, &source);
+        return Ok(vec![(md.0.clone(), String::from_utf8_lossy(&out).into_owned())]);
+    }
+
+    let chapters: Vec<&PathBuf> = Some(&md.0).into_iter().chain(&md.1).collect();
+    let mut contents: Vec<Option<String>> = vec![None; chapters.len()];
+    let mut leading = String::new();
+    let parts = (converter.convert_chapters(source.as_bytes()))?;
+    print_warnings(&mut converter, &rs.0, &source);
+    for (name, out) in parts {
+        let out = String::from_utf8_lossy(&out).into_owned();
+        let name = match name {
+            Some(name) => name,
+            None => {
+                leading = out;
+                continue;
+            }
+        };
+        match chapters.iter().position(|c| config.relative(c) == Path::new(&name)) {
+            Some(i) => contents[i] = Some(out),
+            None => return Err(Error::ConfigError(format!(
+                "{}: `{}` is not one of its chapters", rs.display(), name))),
+        }
+    }
+    let mut files = Vec::new();
+    for (chapter, content) in chapters.into_iter().zip(contents) {
+        match content {
+            Some(content) => files.push((chapter.clone(), content)),
+            None => return Err(Error::ConfigError(format!(
+                "{}: no part is marked as chapter {}", rs.display(), config.relative(chapter).display()))),
+        }
+    }
+    // Anything ahead of the first marker belongs to the first chapter.
+    files[0].1.insert_str(0, &leading);
+    Ok(files)
+}
+
+// Prints the warnings of converting the file at `path`, whose content
+// is `text`.
+fn print_warnings(converter: &mut dyn Converter, path: &Path, text: &str) {
+    for warning in converter.take_warnings() {
+        eprintln!("{}\n", warning.diagnostic().in_file(path, text));
+    }
+}
+
+// Places the problems md2rs found in the files (by chapter) they are
+// in, each given by path and content.
+fn place_md2rs_exception(e: md2rs::Exception, files: &[(&Path, String)]) -> Error {
+    let place = |source: usize, d: Diagnostic| match files.get(source) {
+        Some(&(path, ref text)) => d.in_file(path, text),
+        None => d,
+    };
+    match e {
+        md2rs::Exception::IoError(e) => Error::IoError(e),
+        md2rs::Exception::Warnings(warnings) =>
+            Error::Diagnostics(warnings.into_iter().map(|(source, w)| place(source, w.diagnostic())).collect()),
+        md2rs::Exception::Chunks(source, e) => Error::Diagnostics(vec![place(source, e.diagnostic())]),
+    }
+}
+
+// Converts `md` (with any later chapters) into the content of its
+// twin `rs`, and of each file that its `file=` blocks write to.
+fn md2rs_group(config: &Config, md: &MdPath, rs: &Path) -> Result<(String, Vec<(PathBuf, String)>)> {
+    let (content, files, _) = (md2rs_mapped(config, md, rs))?;
+    Ok((content, files))
+}
+
+// The files generated from a `.md` file, by path and content, and
+// the source map of each.
+type Generated = (String, Vec<(PathBuf, String)>, Vec<(PathBuf, SourceMap)>);
+
+// Like `md2rs_group`, but also returns the source maps of `rs` and of
+// the other files.
+fn md2rs_mapped(config: &Config, md: &MdPath, rs: &Path) -> Result<Generated> {
+    let mut converter = md2rs::Converter::new(config);
+    converter.split_files(&config.relative(md).display().to_string());
+    let sources: Vec<PathBuf> = Some(&md.0).into_iter().chain(&md.1)
+        .map(|p| config.relative(p).to_path_buf())
+        .collect();
+    let mut texts = Vec::new();
+    for p in Some(&md.0).into_iter().chain(&md.1) {
+        // A chapter listed but not yet written is taken as empty.
+        let text = if md.1.is_empty() || p.exists() { (read_file(p))? } else { String::new() };
+        texts.push((p.as_path(), text));
+    }
+    let mut out = Vec::new();
+    let converted = if md.1.is_empty() {
+        converter.convert_all(texts[0].1.as_bytes(), &mut out)
+    } else {
+        let chapters: Vec<(String, String)> = texts.iter()
+            .map(|&(p, ref text)| (config.relative(p).display().to_string(), text.clone()))
+            .collect();
+        converter.convert_chapters(&chapters, &mut out)
+    };
+    let outputs = (converted.map_err(|e| place_md2rs_exception(e, &texts)))?;
+    let mut files = Vec::new();
+    for (file, content) in outputs.pieces {
+        let p = match config.piece_path(&file) {
+            Some(p) => p,
+            None => return Err(Error::ConfigError(format!(
+                "{}: `file={}` must name a `.rs` file under `{}/`, by a relative path without `..`",
+                md.display(), file, config.src_dir()))),
+        };
+        if p == rs {
+            return Err(Error::ConfigError(format!(
+                "{}: a `file=` block names its own twin, {}", md.display(), rs.display())));
+        }
+        files.push((p, String::from_utf8_lossy(&content).into_owned()));
+    }
+    let md_hash = (md.content_hash())?;
+    let mut origins = outputs.origins;
+    let mut maps = vec![(rs.to_path_buf(),
+                         SourceMap::new(md_hash, sources.clone(), origins.remove(&None).unwrap_or_default()))];
+    for (file, lines) in origins {
+        let file = file.expect("the twin's lines are taken above");
+        maps.push((config.resolve(file), SourceMap::new(md_hash, sources.clone(), lines)));
+    }
+    Ok((String::from_utf8_lossy(&out).into_owned(), files, maps))
 }
 
 mod md2rs;
 
 mod rs2md;
 
-fn encode_to_url(code: &str) -> String {
+fn encode_to_url(code: &str, config: &Config) -> String {
     use url::percent_encoding as enc;
     // let new_code: String = enc::utf8_percent_encode(code.trim(), enc::QUERY_ENCODE_SET);
     let new_code: String = enc::utf8_percent_encode(code.trim(), enc::USERINFO_ENCODE_SET).collect();
-    format!("https://play.rust-lang.org/?code={}&version=nightly", new_code)
+    format!("{}?code={}&version={}", config.playground_url(), new_code, config.playground_channel())
 }
 
 #[cfg(test)]
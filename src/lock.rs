@@ -0,0 +1,172 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A 64-bit FNV-1a hash of a file's contents.
+///
+/// This is not meant to resist tampering; it only needs to tell us
+/// whether a file has changed since the last time tango looked at it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ContentHash(pub u64);
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+impl ContentHash {
+    pub fn of_bytes(bytes: &[u8]) -> ContentHash {
+        let mut h = FNV_OFFSET_BASIS;
+        for &b in bytes {
+            h ^= b as u64;
+            h = h.wrapping_mul(FNV_PRIME);
+        }
+        ContentHash(h)
+    }
+
+    pub fn of_file<P: AsRef<Path>>(p: P) -> io::Result<ContentHash> {
+        let mut bytes = Vec::new();
+        (File::open(p)?).read_to_end(&mut bytes)?;
+        Ok(ContentHash::of_bytes(&bytes))
+    }
+
+    pub fn parse(s: &str) -> Option<ContentHash> {
+        u64::from_str_radix(s, 16).ok().map(ContentHash)
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{:016x}", self.0)
+    }
+}
+
+/// The recorded state of one `.rs`/`.md` pair as of the last
+/// successful sync.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub rs_path: PathBuf,
+    pub rs_hash: ContentHash,
+    pub md_path: PathBuf,
+    pub md_hash: ContentHash,
+    /// The other `.rs` files that blocks of the `.md` file are written
+    /// to; `rs_hash` covers these, too.
+    pub pieces: Vec<PathBuf>,
+}
+
+/// Contents of the `tango.lock` file.
+///
+/// Each line records one pair, as four tab-separated fields:
+/// `<rs hash> <md hash> <rs path> <md path>`, followed by the paths of
+/// the pair's pieces (if any). Lines starting with `#` are comments.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Lock {
+    entries: Vec<Entry>,
+}
+
+const HEADER: &str = "\
+# Generated by tango: content hashes of each .rs/.md pair as of the
+# last successful sync. Do not edit by hand.
+";
+
+impl Lock {
+    pub fn new() -> Lock {
+        Lock { entries: Vec::new() }
+    }
+
+    pub fn read<P: AsRef<Path>>(p: P) -> io::Result<Lock> {
+        let mut s = String::new();
+        (File::open(p)?).read_to_string(&mut s)?;
+        Lock::parse(&s)
+    }
+
+    pub fn parse(s: &str) -> io::Result<Lock> {
+        let mut lock = Lock::new();
+        for (i, line) in s.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let entry = match fields[..] {
+                [rs_hash, md_hash, rs_path, md_path, ref pieces @ ..] => {
+                    match (ContentHash::parse(rs_hash), ContentHash::parse(md_hash)) {
+                        (Some(rs_hash), Some(md_hash)) => Some(Entry {
+                            rs_path: PathBuf::from(rs_path),
+                            rs_hash,
+                            md_path: PathBuf::from(md_path),
+                            md_hash,
+                            pieces: pieces.iter().map(PathBuf::from).collect(),
+                        }),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match entry {
+                Some(entry) => lock.entries.push(entry),
+                None => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed tango.lock entry on line {}: {:?}", i + 1, line))),
+            }
+        }
+        Ok(lock)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, p: P) -> io::Result<()> {
+        let mut f = File::create(p)?;
+        self.write_to(&mut f)?;
+        f.flush()
+    }
+
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(HEADER.as_bytes())?;
+        for e in &self.entries {
+            write!(w, "{}\t{}\t{}\t{}",
+                   e.rs_hash, e.md_hash, e.rs_path.display(), e.md_path.display())?;
+            for p in &e.pieces {
+                write!(w, "\t{}", p.display())?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Finds the entry for the pair made of `a` and `b`, in either
+    /// order.
+    pub fn find(&self, a: &Path, b: &Path) -> Option<&Entry> {
+        self.entries.iter().find(|e| {
+            (e.rs_path == a && e.md_path == b) || (e.md_path == a && e.rs_path == b)
+        })
+    }
+
+    /// Looks up the pair made of `source` and `target` (in either
+    /// order), returning their recorded hashes in that same order.
+    pub fn lookup(&self, source: &Path, target: &Path) -> Option<(ContentHash, ContentHash)> {
+        self.find(source, target).map(|e| {
+            if e.rs_path == source {
+                (e.rs_hash, e.md_hash)
+            } else {
+                (e.md_hash, e.rs_hash)
+            }
+        })
+    }
+
+    /// Removes the entry for the pair with the given `.rs` file, if
+    /// any.
+    pub fn remove(&mut self, rs_path: &Path) {
+        self.entries.retain(|e| e.rs_path != rs_path);
+    }
+
+    /// Records the hashes for a pair, replacing any previous entry
+    /// for the same `.rs` file.
+    pub fn record(&mut self, entry: Entry) {
+        self.entries.retain(|e| e.rs_path != entry.rs_path);
+        let pos = self.entries.iter()
+            .position(|e| e.rs_path > entry.rs_path)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(pos, entry);
+    }
+}
@@ -1,19 +1,235 @@
 extern crate tango;
 
+use std::env;
 use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: tango [options] [<command> [<args>]]
+
+commands:
+    sync                    synchronize the .md and .rs files (the default)
+    plan                    report what `sync` would do, without writing anything
+    check                   fail if any .md/.rs pair is out of sync, printing diffs
+    status                  report the state of each .rs/.md pair
+    clean                   remove tango.stamp, tango.lock, tango.base and tango.map
+    watch                   re-sync pairs whenever their files change
+    md2rs <input> [<output>]  convert one .md file to Rust (to stdout by default)
+    rs2md <input> [<output>]  convert one .rs file to Markdown (to stdout by default)
+    map <file>:<line>[:<col>]  translate a position in a generated .rs file to its
+                            .md source, or a position in a .md file to the .rs
+    cargo-diagnostics       copy cargo's JSON messages (--message-format=json) from
+                            stdin to stdout, pointing spans at the .md sources
+
+options:
+    --root <dir>            project root the other paths are relative to (default: .)
+    --src-dir <dir>         directory holding the .rs files (default: src)
+    --lit-dir <dir>         directory holding the .md files (default: src)
+    --mtime                 decide what to regenerate from timestamps alone
+    --emit-rerun-if         print `cargo:rerun-if-changed` lines for each input
+    -n, --dry-run           with `sync`, only report what would be done (same as `plan`)
+    --debounce <ms>         with `watch`, wait for this long without changes before
+                            syncing (default: 200)
+    -h, --help              print this message
+
+Settings are also read from tango.toml and from [package.metadata.tango] in
+Cargo.toml, both under the root; options given here take precedence.
+";
+
+// Exit codes.
+const FAILURE: i32 = 1;
+const USAGE_ERROR: i32 = 2;
+
+enum Command {
+    Sync,
+    Plan,
+    Check,
+    Status,
+    Clean,
+    Watch(Duration),
+    Md2Rs(String, Option<String>),
+    Rs2Md(String, Option<String>),
+    Map(tango::Location),
+    CargoDiagnostics,
+    Help,
+}
+
+fn parse_args(args: Vec<String>) -> Result<(tango::Config, Command), String> {
+    let mut config = tango::Config::new();
+    let mut words = Vec::new();
+    let mut dry_run = false;
+    let mut debounce = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--src-dir" | "--lit-dir" => {
+                let dir = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                if arg == "--src-dir" {
+                    config.set_src_dir(dir);
+                } else {
+                    config.set_lit_dir(dir);
+                }
+            }
+            "--root" => {
+                let dir = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                config.set_root(dir);
+            }
+            "--mtime" => { config.set_sync_mode(tango::SyncMode::Mtime); }
+            "--emit-rerun-if" => { config.emit_rerun_if(); }
+            "-n" | "--dry-run" => dry_run = true,
+            "--debounce" => {
+                let ms = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                let ms = ms.parse().map_err(|_| format!("invalid --debounce value `{}`", ms))?;
+                debounce = Some(Duration::from_millis(ms));
+            }
+            "-h" | "--help" => return Ok((config, Command::Help)),
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+            _ => words.push(arg),
+        }
+    }
+
+    let mut words = words.into_iter();
+    let command = match words.next().as_ref().map(|s| &s[..]) {
+        None | Some("sync") if dry_run => Command::Plan,
+        None | Some("sync") => Command::Sync,
+        _ if dry_run => return Err("--dry-run is only supported by `sync`".to_string()),
+        Some("plan") => Command::Plan,
+        Some("check") => Command::Check,
+        Some("status") => Command::Status,
+        Some("clean") => Command::Clean,
+        Some("watch") => Command::Watch(debounce.take().unwrap_or(tango::watch::DEFAULT_DEBOUNCE)),
+        Some(c @ "md2rs") | Some(c @ "rs2md") => {
+            let input = words.next().ok_or_else(|| format!("`{}` requires an input file", c))?;
+            let output = words.next();
+            if c == "md2rs" { Command::Md2Rs(input, output) } else { Command::Rs2Md(input, output) }
+        }
+        Some("map") => {
+            let location = words.next().ok_or_else(|| "`map` requires a location".to_string())?;
+            let parsed = tango::Location::parse(&location).ok_or_else(|| {
+                format!("invalid location `{}`; expected <file>:<line>[:<col>]", location)
+            })?;
+            Command::Map(parsed)
+        }
+        Some("cargo-diagnostics") => Command::CargoDiagnostics,
+        Some(other) => return Err(format!("unknown command `{}`", other)),
+    };
+    if debounce.is_some() {
+        return Err("--debounce is only supported by `watch`".to_string());
+    }
+    if let Some(extra) = words.next() {
+        return Err(format!("unexpected argument `{}`", extra));
+    }
+    Ok((config, command))
+}
+
+fn convert<F>(input: &str, output: Option<String>, f: F) -> tango::Result<()>
+    where F: FnOnce(File, &mut dyn Write) -> tango::Result<()>
+{
+    let source = (File::open(input))?;
+    match output {
+        Some(path) => f(source, &mut (File::create(path))?),
+        None => {
+            let stdout = io::stdout();
+            let mut lock = stdout.lock();
+            f(source, &mut lock)
+        }
+    }
+}
+
+fn run(config: tango::Config, command: Command) -> tango::Result<()> {
+    match command {
+        Command::Sync => (tango::process_root_with_config(config))?,
+        Command::Plan => {
+            for planned in (tango::plan_with_config(config))? {
+                println!("{}", planned);
+            }
+        }
+        Command::Check => {
+            let result = tango::check_with_config(config);
+            if let Err(tango::Error::OutOfSync(ref mismatches)) = result {
+                for m in mismatches {
+                    print!("{}", m.diff);
+                }
+            }
+            (result)?
+        }
+        Command::Status => {
+            for status in (tango::status_with_config(config))? {
+                let (label, arrow) = match status.state {
+                    tango::PairState::RsOnly => ("rs only", "->"),
+                    tango::PairState::MdOnly => ("md only", "<-"),
+                    tango::PairState::Untracked => ("untracked", "<>"),
+                    tango::PairState::InSync => ("in sync", "=="),
+                    tango::PairState::RsChanged => ("rs changed", "->"),
+                    tango::PairState::MdChanged => ("md changed", "<-"),
+                    tango::PairState::BothChanged => ("both changed", "<>"),
+                };
+                println!("{:<13} {} {} {}", label,
+                         status.rs_path.display(), arrow, status.md_path.display());
+            }
+        }
+        Command::Clean => (tango::clean_with_config(config))?,
+        Command::Watch(debounce) => {
+            let mut watcher = (tango::watch::Watcher::new(config))?;
+            watcher.set_debounce(debounce);
+            (watcher.run(tango::watch::DEFAULT_POLL_INTERVAL))?
+        }
+        Command::Md2Rs(input, output) => {
+            let config = (config.load_files())?;
+            (convert(&input, output, |r, w| tango::md2rs_with_config(&config, r, w)))?
+        }
+        Command::Rs2Md(input, output) => {
+            let config = (config.load_files())?;
+            (convert(&input, output, |r, w| tango::rs2md_with_config(&config, r, w)))?
+        }
+        Command::Map(location) => {
+            match (tango::map_location(config, &location))? {
+                Some(mapped) => println!("{}", mapped),
+                None => {
+                    eprintln!("no source map covers {}; run `tango` to record one", location);
+                    process::exit(FAILURE);
+                }
+            }
+        }
+        Command::CargoDiagnostics => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            (tango::rewrite_cargo_diagnostics(config, stdin.lock(), stdout.lock()))?
+        }
+        Command::Help => print!("{}", USAGE),
+    }
+    Ok(())
+}
 
 pub fn main() {
-    tango::process_root().unwrap_or_else(|e| {
+    let (config, command) = match parse_args(env::args().skip(1).collect()) {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            process::exit(USAGE_ERROR);
+        }
+    };
+    if let Err(e) = run(config, command) {
+        // Diagnostics carry their own place and severity, as rustc's.
+        if let tango::Error::Diagnostics(ref diagnostics) = e {
+            for d in diagnostics {
+                eprintln!("{}\n", d);
+            }
+            process::exit(FAILURE);
+        }
         let mut cause: Option<&dyn Error> = Some(&e);
         while let Some(c) = cause {
             let next_cause = c.source();
             if next_cause.is_some() {
-                println!("{}, due to", c);
+                eprintln!("{}, due to", c);
             } else {
-                println!("root error: {}", c);
+                eprintln!("root error: {}", c);
             }
             cause = next_cause;
         }
-        panic!("IO error {}", e);
-    })
+        process::exit(FAILURE);
+    }
 }
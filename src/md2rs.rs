@@ -1,30 +1,117 @@
-use std::io::{self, BufRead, Write};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::mem;
 
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+
+/// Converts Markdown to Rust. The lines fed are held until a fenced
+/// block closes, then converted; from the first chunk of a document
+/// on, they are held until `finish`.
 pub struct Converter {
+    config: Config,
+    // The lines fed since the output was last brought up to date.
+    // Which lines are Rust code can depend on what follows them (a
+    // fence is only a fence if it is closed, or the document ends),
+    // so they wait until a top-level fenced block closes: what
+    // follows it parses just as a document of its own would.
+    pending: String,
+    // The number of lines converted before those pending.
+    converted: usize,
+    // Whether the rest of the document waits for its end, as it
+    // defines or refers to chunks: where the code of a chunk goes
+    // depends on references that may come later.
+    whole: bool,
     state: State,
     blank_line_count: usize,
+    // Where each of the pending blank lines came from.
+    blank_origins: Vec<Origin>,
     buffered_lines: String,
-    warnings: Vec<Warning>,
+    // Whether a code block was the last thing written, with at most
+    // blank lines since: the next must then be marked as a block of
+    // its own, or the two would be read back as one.
+    follows_code: bool,
+    // Whether the code of the current block is to be commented out,
+    // as it is not expected to compile.
+    commented: bool,
+    // The chunks defined in the document, and those referred to.
+    chunks: Definitions,
+    referenced: BTreeSet<String>,
+    // When blocks with a `file=` attribute are written to the files
+    // they name: the `.md` file's path, for the markers, and the code
+    // written to each file so far.
+    pieces: Option<(String, BTreeMap<String, Vec<u8>>)>,
+    // The file the code of the current block goes to, if not the twin.
+    piece: Option<String>,
+    // The position of each input line, and that of the line being
+    // converted, with the shift from the column of the text given to
+    // `nonblank_line` to the column of the input line.
+    positions: Vec<Origin>,
+    origin: Origin,
+    // Where each line written came from, by the file written to.
+    origins: BTreeMap<Option<String>, Vec<Origin>>,
+    // The warnings so far, with the index of the chapter each is in.
+    warnings: Vec<(usize, Warning)>,
 }
 
-use super::Warning;
+use super::{Config, Warning};
+use diagnostic::column;
+use chapters;
+use chunks::{self, ChunkError, Definitions};
+use info::Info;
+use pieces;
+use source_map::Origin;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum State { MarkdownBlank, MarkdownText, MarkdownMeta, Rust, }
 impl Converter {
-    pub fn new() -> Converter {
+    pub fn new(config: &Config) -> Converter {
         Converter {
+            config: config.clone(),
+            pending: String::new(),
+            converted: 0,
+            whole: false,
             state: State::MarkdownBlank,
             blank_line_count: 0,
+            blank_origins: vec![],
             buffered_lines: String::new(),
+            follows_code: false,
+            commented: false,
+            chunks: Definitions::new(),
+            referenced: BTreeSet::new(),
+            pieces: None,
+            piece: None,
+            positions: vec![],
+            origin: Origin::default(),
+            origins: BTreeMap::new(),
             warnings: vec![],
         }
     }
+
+    /// Writes the code of blocks with a `file=` attribute to the files
+    /// they name (see `convert_all`), rather than in place. `origin`
+    /// names the `.md` file in the markers between pieces.
+    pub(crate) fn split_files(&mut self, origin: &str) -> &mut Converter {
+        self.pieces = Some((origin.to_string(), BTreeMap::new()));
+        self
+    }
+}
+
+/// What a conversion produces besides the `.rs` file itself.
+pub struct Outputs {
+    /// The content of each other file that code was written to, by
+    /// the path given in its `file=` attributes.
+    pub pieces: BTreeMap<String, Vec<u8>>,
+    /// Where each line written came from: the lines of the `.rs` file
+    /// under `None`, and those of each other file under its path. The
+    /// sources are the chapters, in order, or just the one `.md` file.
+    pub origins: BTreeMap<Option<String>, Vec<Origin>>,
 }
 
 pub enum Exception {
     IoError(io::Error),
-    Warnings(Vec<Warning>),
+    // With the index of the chapter each is in, as for `Origin`.
+    Warnings(Vec<(usize, Warning)>),
+    Chunks(usize, ChunkError),
 }
 
 impl From<io::Error> for Exception {
@@ -33,61 +120,418 @@ impl From<io::Error> for Exception {
     }
 }
 
+// How a line of the input relates to the Rust code blocks in it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Line {
+    Text,
+    // Belongs to some other code block, e.g. one fenced as "```toml"
+    // or nested in a block quote; carried through as text, verbatim.
+    Verbatim,
+    // Opens a Rust code block with the given info string.
+    Open(Info),
+    // Holds code, once up to the given number of leading spaces (the
+    // indentation of the opening fence) are removed.
+    Code(usize),
+    Close,
+    // Belongs to the definition of a chunk that is referred to, so
+    // its code is emitted where it is referred to instead.
+    Skip,
+    // Starts the chapter with the given name, when converting a
+    // sequence of chapters; not a line of the input.
+    Chapter(String),
+}
+
+impl super::Converter for Converter {
+    fn feed_line(&mut self, line: &str, w: &mut dyn Write) -> super::Result<()> {
+        // Any carriage returns ending the line are taken as part of
+        // the line ending (as `str::lines` takes one), since the
+        // output has none.
+        self.pending.push_str(line.trim_end_matches('\r'));
+        self.pending.push('\n');
+        if !self.whole && (closes(line, "```") || closes(line, "~~~")) {
+            (self.convert_block(w))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, w: &mut dyn Write) -> super::Result<()> {
+        self.convert_input(w).map_err(From::from)
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        self.warnings.drain(..).map(|(_, warning)| warning).collect()
+    }
+}
+
 impl Converter {
-    pub fn convert<R:io::Read, W:io::Write>(mut self, r:R, mut w:W) -> Result<(), Exception> {
-        let source = io::BufReader::new(r);
-        for line in source.lines() {
-            let line = (line)?;
-            (self.handle(&line, &mut w))?;
+    // Converts the lines pending, if the last of them closes a
+    // top-level fenced block and none of them have to do with chunks.
+    fn convert_block(&mut self, w: &mut dyn Write) -> Result<(), Exception> {
+        let text = mem::take(&mut self.pending);
+        let (kinds, closed) = self.classify(&text);
+        let lines: Vec<&str> = text.lines().collect();
+        if closed {
+            if !uses_chunks(&lines, &kinds) {
+                return self.convert_lines(lines, kinds, w);
+            }
+            self.whole = true;
+        }
+        self.pending = text;
+        Ok(())
+    }
+
+    // Converts the lines pending, which end the input.
+    fn convert_input(&mut self, w: &mut dyn Write) -> Result<(), Exception> {
+        let text = mem::take(&mut self.pending);
+        let (kinds, _) = self.classify(&text);
+        self.convert_lines(text.lines().collect(), kinds, w)
+    }
+
+    /// Takes the outputs of the conversion besides the `.rs` file.
+    fn outputs(&mut self) -> Outputs {
+        Outputs {
+            pieces: self.pieces.as_mut().map(|&mut (_, ref mut pieces)| mem::take(pieces)).unwrap_or_default(),
+            origins: mem::take(&mut self.origins),
+        }
+    }
+
+    /// Converts all of `r`, returning the content of each other file
+    /// that code was written to, and where each line came from.
+    pub(crate) fn convert_all<R:io::Read, W:io::Write>(mut self, mut r:R, mut w:W) -> Result<Outputs, Exception> {
+        let mut text = String::new();
+        (r.read_to_string(&mut text))?;
+        self.pending = unix_lines(&text);
+        (self.convert_input(&mut w))?;
+        (self.check_warnings())?;
+        Ok(self.outputs())
+    }
+
+    /// Converts a sequence of chapters, given by name and content,
+    /// into a single `.rs` file, with a marker line starting each
+    /// chapter (see `chapters`). Returns the other outputs, as
+    /// `convert_all` does.
+    pub(crate) fn convert_chapters<W:io::Write>(mut self, chapters: &[(String, String)], mut w:W)
+                                        -> Result<Outputs, Exception> {
+        let texts: Vec<String> = chapters.iter().map(|(_, text)| unix_lines(text)).collect();
+        let mut lines = Vec::new();
+        let mut kinds = Vec::new();
+        for ((name, _), text) in chapters.iter().zip(&texts) {
+            lines.push("");
+            kinds.push(Line::Chapter(name.clone()));
+            // Each chapter is parsed on its own, so that e.g. a fence
+            // left open at the end of one does not run into the next.
+            lines.extend(text.lines());
+            kinds.extend(self.classify(text).0);
         }
+        (self.convert_lines(lines, kinds, &mut w))?;
+        (self.check_warnings())?;
+        Ok(self.outputs())
+    }
+
+    fn check_warnings(&mut self) -> Result<(), Exception> {
         if self.warnings.is_empty() {
             Ok(())
         } else {
-            Err(Exception::Warnings(self.warnings))
+            Err(Exception::Warnings(mem::take(&mut self.warnings)))
         }
     }
 
-    pub fn handle(&mut self, line: &str, w: &mut dyn Write) -> io::Result<()> {
-        let str9 = line.chars().take(9).collect::<String>();
-        let str7 = line.chars().take(7).collect::<String>();
-        match (self.state, &str7[..], &str9[..]) {
-            (State::MarkdownBlank, "```rust", _) |
-            (State::MarkdownText, "```rust", _) => {
-                self.buffered_lines = String::new();
-                let rest =  &line.chars().skip(7).collect::<String>();
-                if rest != "" {
-                    (self.transition(w, State::MarkdownMeta))?;
-                    (self.meta_note(&rest, w))?;
+    fn convert_lines(&mut self, lines: Vec<&str>, mut kinds: Vec<Line>, w: &mut dyn Write)
+                     -> Result<(), Exception> {
+        self.positions = positions(&kinds, self.converted);
+        self.converted += kinds.len();
+        self.check_fences(&lines, &kinds);
+        (self.collect_chunks(&lines, &mut kinds))?;
+        for (i, (line, kind)) in lines.into_iter().zip(kinds).enumerate() {
+            self.origin = self.positions[i];
+            let opens_piece = match kind {
+                Line::Open(ref info) => self.piece_for(info),
+                Line::Chapter(ref name) => {
+                    // Pieces name the chapter they come from.
+                    if let Some((ref mut origin, _)) = self.pieces {
+                        *origin = name.clone();
+                    }
+                    self.piece = None;
+                    None
                 }
-                self.transition(w, State::Rust)
+                _ => None,
+            };
+            let close = kind == Line::Close;
+            match self.piece.clone() {
+                Some(file) => {
+                    let mut code = self.take_piece(&file);
+                    (self.handle(line, kind, &mut code))?;
+                    self.put_piece(file, code);
+                }
+                None => (self.handle(line, kind, w))?,
+            }
+            if let Some(file) = opens_piece {
+                let mut code = self.take_piece(&file);
+                let start = pieces::start(self.pieces.as_ref().map_or("", |p| &p.0[..]));
+                self.piece = Some(file.clone());
+                (self.put(&mut code, "", &start))?;
+                self.put_piece(file, code);
+            } else if close {
+                self.piece = None;
+            }
+        }
+        Ok(())
+    }
+
+    // The file that the code of a block goes to, if not the twin. The
+    // code of a chunk that is referred to goes where it is referred to.
+    fn piece_for(&self, info: &Info) -> Option<String> {
+        if self.pieces.is_none() || info.chunk().is_some_and(|name| self.referenced.contains(name)) {
+            return None;
+        }
+        info.file().map(|file| file.to_string())
+    }
+
+    fn take_piece(&mut self, file: &str) -> Vec<u8> {
+        let (_, ref mut pieces) = *self.pieces.as_mut().expect("splitting files");
+        mem::take(pieces.entry(file.to_string()).or_default())
+    }
+
+    fn put_piece(&mut self, file: String, code: Vec<u8>) {
+        let (_, ref mut pieces) = *self.pieces.as_mut().expect("splitting files");
+        pieces.insert(file, code);
+    }
+
+    // Finds the Rust code blocks in `text` with a CommonMark parse,
+    // so that e.g. a "```rust" line inside some other fenced block,
+    // or in the middle of a paragraph, is not taken for an opening
+    // fence. Only top-level fences are taken as Rust: code blocks in
+    // block quotes and list items are left as Markdown, since the
+    // `.rs` file has no way to express the enclosing container. Also
+    // returns whether the last line of `text` closes a top-level
+    // fenced block.
+    fn classify(&self, text: &str) -> (Vec<Line>, bool) {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        let line_of = |offset: usize| match starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        let mut kinds = vec![Line::Text; lines.len()];
+        let mut ends_block = false;
+
+        let mut depth = 0;
+        for (event, range) in Parser::new(text).into_offset_iter() {
+            match event {
+                Event::Start(Tag::BlockQuote) | Event::Start(Tag::List(_)) => depth += 1,
+                Event::End(Tag::BlockQuote) | Event::End(Tag::List(_)) => depth -= 1,
+                Event::Start(Tag::CodeBlock(ref kind)) => {
+                    let open = line_of(range.start);
+                    let last = line_of(range.end - 1).min(lines.len() - 1);
+                    // The info string is taken as written, rather than
+                    // as parsed, which has its backslash escapes and
+                    // entities resolved: the `.rs` file must carry it
+                    // unchanged.
+                    let info = match *kind {
+                        CodeBlockKind::Fenced(_) if depth == 0 =>
+                            Info::parse(info_of(lines[open]), &self.config.fence_languages()),
+                        _ => None,
+                    };
+                    // A block with no code is carried through as text,
+                    // as the `.rs` file has nowhere to put it; but for
+                    // the definition of a chunk, which may be referred
+                    // to all the same (see `collect_chunks`).
+                    let closed = last > open && closes(lines[last], &fence_of(lines[open]).1);
+                    if let CodeBlockKind::Fenced(_) = *kind {
+                        ends_block |= depth == 0 && closed && last == lines.len() - 1;
+                    }
+                    let end = if closed { last } else { last + 1 };
+                    let empty = lines[open + 1..end].iter().all(|line| line.trim().is_empty());
+                    let info = match info {
+                        Some(ref info) if empty && info.chunk().is_none() => None,
+                        Some(info) => Some(info),
+                        None => None,
+                    };
+                    let info = match info {
+                        Some(info) => info,
+                        None => {
+                            for kind in &mut kinds[open..=last] {
+                                *kind = Line::Verbatim;
+                            }
+                            continue;
+                        }
+                    };
+                    let indent = fence_of(lines[open]).0;
+                    kinds[open] = Line::Open(info);
+                    for kind in &mut kinds[open + 1..end] {
+                        *kind = Line::Code(indent);
+                    }
+                    if closed {
+                        kinds[last] = Line::Close;
+                    }
+                }
+                _ => {}
+            }
+        }
+        (kinds, ends_block)
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        self.warnings.push((self.origin.source, warning));
+    }
+
+    // Warns of each Rust code block left open, which runs on to the end
+    // of the document (or chapter).
+    fn check_fences(&mut self, lines: &[&str], kinds: &[Line]) {
+        let mut unclosed = Vec::new();
+        let mut open = None;
+        for (i, kind) in kinds.iter().enumerate() {
+            match *kind {
+                Line::Open(_) => open = Some(i),
+                Line::Code(_) => {}
+                Line::Close => open = None,
+                _ => unclosed.extend(open.take()),
+            }
+        }
+        unclosed.extend(open);
+        for i in unclosed {
+            let Origin { source, line, .. } = self.positions[i];
+            self.warnings.push((source, Warning::UnclosedFence { line, column: fence_of(lines[i]).0 + 1 }));
+        }
+    }
+
+    // Records the chunks defined in the document, checking that every
+    // reference (in a block that is compiled) is to a chunk defined
+    // exactly once, and that no chunk refers to itself.
+    fn collect_chunks(&mut self, lines: &[&str], kinds: &mut [Line]) -> Result<(), Exception> {
+        // The chapter, line and column of the given byte of line `i`.
+        let positions = &self.positions;
+        let at = |i: usize, byte: usize| (positions[i].source, positions[i].line, column(lines[i], byte));
+        let mut defs = Definitions::new();
+        let mut uses = Vec::new();
+        let mut defining: Option<String> = None;
+        let mut compiled = true;
+        for (i, kind) in kinds.iter().enumerate() {
+            match *kind {
+                Line::Open(ref info) => {
+                    compiled = info.compiles();
+                    defining = info.chunk().map(|name| name.to_string());
+                    if let Some(ref name) = defining {
+                        if defs.contains_key(name) {
+                            let (source, line, column) = at(i, fence_of(lines[i]).0);
+                            let name = name.clone();
+                            return Err(Exception::Chunks(source, ChunkError::Duplicate { name, line, column }));
+                        }
+                        defs.insert(name.clone(), (i + 1, Vec::new()));
+                    }
+                }
+                Line::Code(indent) => {
+                    let code = strip_indent(lines[i], indent);
+                    if let Some(ref name) = defining {
+                        defs.get_mut(name).unwrap().1.push(code.to_string());
+                    }
+                    if let (true, Some((indent, name))) = (compiled, chunks::reference(code)) {
+                        uses.push((at(i, lines[i].len() - code.len() + indent.len()), name.to_string()));
+                    }
+                }
+                _ => defining = None,
+            }
+        }
+        if defs.is_empty() {
+            return Ok(());
+        }
+        for ((source, line, column), name) in uses {
+            if !defs.contains_key(&name) {
+                return Err(Exception::Chunks(source, ChunkError::Undefined { name, line, column }));
             }
-            (State::MarkdownBlank, _, "```{.rust") |
-            (State::MarkdownText, _, "```{.rust") => {
+            self.referenced.insert(name);
+        }
+        if let Err(names) = chunks::check_cycles(&defs) {
+            let open = defs[&names[0]].0 - 1;
+            let (source, line, column) = at(open, fence_of(lines[open]).0);
+            return Err(Exception::Chunks(source, ChunkError::Cyclic { names, line, column }));
+        }
+
+        let (mut skipping, mut empty) = (false, false);
+        for kind in kinds.iter_mut() {
+            if let Line::Open(ref info) = *kind {
+                let name = info.chunk();
+                skipping = name.is_some_and(|name| self.referenced.contains(name));
+                // A chunk with no code that is not referred to is a
+                // block like any other, and so carried through as text.
+                empty = !skipping && name.is_some_and(|name| defs[name].1.iter().all(|l| l.trim().is_empty()));
+            }
+            match *kind {
+                Line::Open(_) | Line::Code(_) | Line::Close if empty => *kind = Line::Verbatim,
+                Line::Code(_) | Line::Close if skipping => *kind = Line::Skip,
+                _ => {}
+            }
+        }
+        self.chunks = defs;
+        Ok(())
+    }
+
+    fn handle(&mut self, line: &str, kind: Line, w: &mut dyn Write) -> io::Result<()> {
+        match kind {
+            Line::Open(info) => {
                 self.buffered_lines = String::new();
-                let rest =  &line.chars().skip(9).collect::<String>();
-                if rest != "" {
-                    (self.transition(w, State::MarkdownMeta))?;
-                    (self.meta_note(&format!(" {{{}", rest), w))?;
+                match info.note() {
+                    Some(note) => {
+                        (self.transition(w, State::MarkdownMeta))?;
+                        (self.meta_note(&note, w))?;
+                    }
+                    None if self.follows_code => {
+                        (self.transition(w, State::MarkdownMeta))?;
+                        (self.put(w, "//@@", ""))?;
+                    }
+                    None => {}
+                }
+                self.follows_code = false;
+                self.commented = !info.compiles();
+                (self.transition(w, State::Rust))?;
+                if info.chunk().is_some_and(|name| self.referenced.contains(name)) {
+                    (self.transition(w, State::MarkdownBlank))?;
+                }
+                Ok(())
+            }
+            Line::Skip => Ok(()),
+            Line::Chapter(name) => {
+                if self.state == State::Rust {
+                    (self.transition(w, State::MarkdownBlank))?;
                 }
-                self.transition(w, State::Rust)
+                // As at the end of a file, trailing blank lines are
+                // dropped.
+                self.blank_line_count = 0;
+                self.blank_origins.clear();
+                self.state = State::MarkdownBlank;
+                self.follows_code = false;
+                self.put(w, "", &chapters::start(&name))
             }
-            (State::Rust, "```", _) => {
+            Line::Close => {
+                self.follows_code = true;
                 self.transition(w, State::MarkdownBlank)
             }
+            Line::Code(indent) => {
+                let code = strip_indent(line, indent);
+                self.origin.shift = (line.len() - code.len()) as isize;
+                self.code_line(code, "", w)
+            }
 
             // FIXME: accum blank lines and only emit them with
             // prefix if there's no state transition; otherwise
             // emit them with no prefix. (This is in part the
             // motivation for the `fn finish_section` design.)
-            (_, "", _) => {
+            Line::Text | Line::Verbatim if line.is_empty() => {
                 self.blank_line(w)
             }
 
-            _ => {
+            Line::Verbatim => {
+                self.nonblank_line(line, w)
+            }
+
+            Line::Text => {
                 // HACK: if we find anything that looks like a markdown-named playpen link ...
-                let open_pat = "[";
-                let close_pat = "]: https://play.rust-lang.org/?code=";
-                if let (Some(open), Some(close)) = (line.find(open_pat), line.find(close_pat)) {
+                let close_pat = format!("]: {}?code=", self.config.playground_url());
+                let link = line.find(&close_pat)
+                    .and_then(|close| line[..close].find('[').map(|open| (open, close)))
+                    .filter(|&(open, close)| open + 1 < close);
+                if let Some((open, close)) = link {
                     // ... then we assume it is associated with the (hopefully immediately preceding)
                     // code block, so we emit a `//@@@` named tag for that code block.
 
@@ -95,12 +539,15 @@ impl Converter {
                     // up with emitted url. If non-match, then warn
                     // the user, and suggest they re-run `tango` after
                     // touching the file to generate matching url.
-                    let expect = super::encode_to_url(&self.buffered_lines);
+                    let expect = super::encode_to_url(&self.buffered_lines, &self.config);
                     let actual = &line[(close+3)..];
-                    if expect != actual {
-                        self.warnings.push(Warning::EncodedUrlMismatch {
+                    if self.config.check_urls() && expect != actual {
+                        let column = column(line, close + 3);
+                        self.warn(Warning::EncodedUrlMismatch {
                             actual: actual.to_string(),
-                            expect: expect
+                            expect,
+                            line: self.origin.line,
+                            column,
                         })
                     }
                     self.name_block(line, &line[open+1..close], w)
@@ -111,28 +558,98 @@ impl Converter {
         }
     }
 
-    pub fn meta_note(&mut self, note: &str, w: &mut dyn Write) -> io::Result<()> {
-        assert!(note != "");
+    fn meta_note(&mut self, note: &str, w: &mut dyn Write) -> io::Result<()> {
         self.nonblank_line(note, w)
     }
 
-    pub fn name_block(&mut self, _line: &str, name: &str, w: &mut dyn Write) -> io::Result<()> {
-        assert!(name != "");
-        writeln!(w, "//@@@ {}", name)
+    fn name_block(&mut self, _line: &str, name: &str, w: &mut dyn Write) -> io::Result<()> {
+        self.follows_code = false;
+        let blank_prefix = if self.state == State::MarkdownText { "//@" } else { "" };
+        (self.blank_lines(w, blank_prefix))?;
+        self.put(w, "//@@@ ", name)
     }
 
-    pub fn nonblank_line(&mut self, line: &str, w: &mut dyn Write) -> io::Result<()> {
+    // Emits a line of code, indented by `indent` (when it comes from
+    // the expansion of a chunk).
+    fn code_line(&mut self, line: &str, indent: &str, w: &mut dyn Write) -> io::Result<()> {
+        if line == "#" {
+            self.hidden_line("", indent, w)
+        } else if let Some(code) = line.strip_prefix("# ") {
+            self.origin.shift += 2;
+            self.hidden_line(code, indent, w)
+        } else if let Some(code) = line.strip_prefix('#').filter(|code| code.starts_with('#')) {
+            // As in rustdoc, "##" escapes a "#" that is to be shown.
+            self.origin.shift += 1;
+            self.nonblank_line(&format!("{}{}", indent, code), w)
+        } else if line.trim().is_empty() {
+            // Whitespace alone would not survive in the `.rs` file.
+            self.blank_line(w)
+        } else if let Some((more, name)) = self.expandable(line) {
+            self.expand(name, &format!("{}{}", indent, more), w)
+        } else {
+            if !self.commented && line.trim_start().starts_with("//@") {
+                // (The column is that of the `.md` line, but for code
+                // from a chunk, whose fence's indentation is lost.)
+                let at = line.find("//@").unwrap_or(0);
+                let column = column(line, at) + self.origin.shift.max(0) as usize;
+                self.warn(Warning::MarkerInCode { code: line.to_string(), line: self.origin.line, column });
+            }
+            self.nonblank_line(&format!("{}{}", indent, line), w)
+        }
+    }
+
+    fn expandable<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str)> {
+        chunks::reference(line).filter(|&(_, name)| !self.commented && self.chunks.contains_key(name))
+    }
+
+    fn expand(&mut self, name: &str, indent: &str, w: &mut dyn Write) -> io::Result<()> {
+        // The playground link of a block covers its code as written,
+        // with the reference rather than the expansion.
+        let buffered = self.buffered_lines.clone();
+        (self.marker(&chunks::expansion_start(indent, name), w))?;
+        let (open, body) = self.chunks[name].clone();
+        // Each line of the expansion maps to the line of the chunk's
+        // definition it came from.
+        let origin = self.origin;
+        for (i, line) in body.iter().enumerate() {
+            self.origin = Origin { shift: -(indent.len() as isize), ..self.positions[open + i] };
+            (self.code_line(line, indent, w))?;
+        }
+        self.origin = origin;
+        (self.marker(&chunks::expansion_end(indent), w))?;
+        self.buffered_lines = format!("{}\n{}<<{}>>", buffered, indent, name);
+        Ok(())
+    }
+
+    // Writes a marker line within a code block, after any blank lines
+    // pending.
+    fn marker(&mut self, line: &str, w: &mut dyn Write) -> io::Result<()> {
+        (self.blank_lines(w, ""))?;
+        self.put(w, "", line)
+    }
+
+    // A line hidden from rendered docs as in rustdoc, i.e. starting
+    // with "# " (or just "#" where blank), is compiled like any
+    // other, following a `//@#` line marking it as hidden.
+    fn hidden_line(&mut self, code: &str, indent: &str, w: &mut dyn Write) -> io::Result<()> {
+        (self.marker(&format!("{}//@#", indent), w))?;
+        if code.is_empty() {
+            self.buffered_lines.push('\n');
+            self.put(w, "", "")
+        } else {
+            self.nonblank_line(&format!("{}{}", indent, code), w)
+        }
+    }
+
+    fn nonblank_line(&mut self, line: &str, w: &mut dyn Write) -> io::Result<()> {
         let (blank_prefix, line_prefix) = match self.state {
             State::MarkdownBlank => ("", "//@ "),
             State::MarkdownText => ("//@", "//@ "),
             State::MarkdownMeta => ("//@", "//@@"),
+            State::Rust if self.commented => ("", "// "),
             State::Rust => ("", ""),
         };
-        for _ in 0..self.blank_line_count {
-            (writeln!(w, "{}", blank_prefix))?;
-
-        }
-        self.blank_line_count = 0;
+        (self.blank_lines(w, blank_prefix))?;
 
         match self.state {
             State::MarkdownBlank =>
@@ -145,50 +662,137 @@ impl Converter {
             }
         }
 
-        writeln!(w, "{}{}", line_prefix, line)
+        self.put(w, line_prefix, line)
+    }
+
+    // Writes `prefix` followed by `line`, recording where it came from.
+    fn put(&mut self, w: &mut dyn Write, prefix: &str, line: &str) -> io::Result<()> {
+        (writeln!(w, "{}{}", prefix, line))?;
+        let origin = Origin { shift: self.origin.shift - prefix.len() as isize, ..self.origin };
+        self.origins.entry(self.piece.clone()).or_default().push(origin);
+        Ok(())
     }
 
     fn blank_line(&mut self, _w: &mut dyn Write) -> io::Result<()> {
-        match self.state {
-            State::Rust => {
-                self.buffered_lines.push_str("\n");
-            }
-            State::MarkdownBlank |
-            State::MarkdownMeta |
-            State::MarkdownText => {}
-        }
         self.blank_line_count += 1;
+        self.blank_origins.push(self.origin);
         Ok(())
     }
 
-    fn finish_section(&mut self, w: &mut dyn Write) -> io::Result<()> {
-        for _ in 0..self.blank_line_count {
-            (writeln!(w, ""))?;
+    // Writes the pending blank lines, each with `prefix`.
+    fn blank_lines(&mut self, w: &mut dyn Write, prefix: &str) -> io::Result<()> {
+        let origin = self.origin;
+        let origins = mem::take(&mut self.blank_origins);
+        for i in 0..self.blank_line_count {
+            // Blank lines within a block are part of its code.
+            if self.state == State::Rust {
+                self.buffered_lines.push('\n');
+            }
+            self.origin = origins.get(i).cloned().unwrap_or(origin);
+            (self.put(w, prefix, ""))?;
         }
+        self.origin = origin;
         self.blank_line_count = 0;
         Ok(())
     }
 
+    fn finish_section(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        self.blank_lines(w, "")
+    }
+
+    // Moving to the state already in is nothing.
     fn transition(&mut self, w: &mut dyn Write, s: State) -> io::Result<()> {
+        if s == self.state {
+            return Ok(());
+        }
         match s {
             State::MarkdownMeta => {
-                assert!(self.state != State::Rust);
                 (self.finish_section(w))?;
             }
             State::Rust => {
-                assert!(self.state != State::Rust);
                 self.buffered_lines = String::new();
             }
             State::MarkdownText => {
-                assert_eq!(self.state, State::MarkdownBlank);
+                self.follows_code = false;
                 (self.finish_section(w))?;
             }
             State::MarkdownBlank => {
-                assert_eq!(self.state, State::Rust);
-                (self.finish_section(w))?;
+                // Blank lines at the end of a code block are left
+                // pending, as those after it are: the `.rs` file has
+                // no way to tell them apart.
             }
         }
         self.state = s;
         Ok(())
     }
 }
+
+// Ends each line of `text` with a "\n" alone: any carriage returns
+// ahead of it are taken as part of the line ending (as `str::lines`
+// takes one), since the output has none.
+fn unix_lines(text: &str) -> String {
+    text.lines().map(|line| format!("{}\n", line.trim_end_matches('\r'))).collect()
+}
+
+// The position of each input line: its line number (from 1) within
+// its chapter, and the index of the chapter. A chapter's marker has
+// the position of the chapter's first line. The lines follow the
+// first `converted` lines of the input.
+fn positions(kinds: &[Line], converted: usize) -> Vec<Origin> {
+    let (mut chapters, mut source, mut line) = (0, 0, converted);
+    kinds.iter().map(|kind| {
+        if let Line::Chapter(_) = *kind {
+            source = chapters;
+            chapters += 1;
+            line = 0;
+            Origin { source, line: 1, shift: 0 }
+        } else {
+            line += 1;
+            Origin { source, line, shift: 0 }
+        }
+    }).collect()
+}
+
+// Whether the code blocks of `lines`, as classified by `kinds`,
+// define or refer to chunks.
+fn uses_chunks(lines: &[&str], kinds: &[Line]) -> bool {
+    lines.iter().zip(kinds).any(|(line, kind)| match *kind {
+        Line::Open(ref info) => info.chunk().is_some(),
+        Line::Code(indent) => chunks::reference(strip_indent(line, indent)).is_some(),
+        _ => false,
+    })
+}
+
+// Returns the indentation and the run of backticks or tildes that
+// open the fenced code block on `line`.
+fn fence_of(line: &str) -> (usize, String) {
+    let rest = line.trim_start_matches(' ');
+    let c = rest.chars().next().unwrap_or('`');
+    let fence: String = rest.chars().take_while(|&d| d == c).collect();
+    (line.len() - rest.len(), fence)
+}
+
+// The info string of the opening fence `line`, verbatim.
+fn info_of(line: &str) -> &str {
+    let (indent, fence) = fence_of(line);
+    line[indent + fence.len()..].trim_matches(|c: char| c == ' ' || c == '\t')
+}
+
+// Whether `line` closes a code block opened by `fence`: per
+// CommonMark, a run of at least as many of the same character,
+// indented by at most three spaces and followed only by whitespace.
+fn closes(line: &str, fence: &str) -> bool {
+    let rest = line.trim_start_matches(' ');
+    let c = fence.chars().next().unwrap_or('`');
+    let after = rest.trim_start_matches(c);
+    line.len() - rest.len() <= 3
+        && rest.len() - after.len() >= fence.len()
+        && after.trim().is_empty()
+}
+
+// Removes up to `indent` leading spaces (the indentation of the
+// opening fence) from a line of code.
+fn strip_indent(line: &str, indent: usize) -> &str {
+    let spaces = line.len() - line.trim_start_matches(' ').len();
+    &line[spaces.min(indent)..]
+}
@@ -0,0 +1,117 @@
+// Line-based three-way merge, used when both the `.md` and the `.rs`
+// of a pair have been edited since the last sync.
+//
+// Both sides are compared (in their `.rs` form) against the base
+// recorded at the last sync; regions changed on only one side take
+// that side's lines, and regions changed differently on both sides
+// are emitted between conflict markers.
+
+use diff;
+
+pub const CONFLICT_START: &str = "<<<<<<<";
+pub const CONFLICT_SEP: &str = "=======";
+pub const CONFLICT_END: &str = ">>>>>>>";
+
+pub struct Merged {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+pub fn merge3(base: &str, ours: &str, theirs: &str,
+              ours_label: &str, theirs_label: &str) -> Merged {
+    let base: Vec<&str> = base.lines().collect();
+    let ours: Vec<&str> = ours.lines().collect();
+    let theirs: Vec<&str> = theirs.lines().collect();
+
+    let mut hunks: Vec<(Side, Hunk)> = Vec::new();
+    hunks.extend(diff(&base, &ours).into_iter().map(|h| (Side::Ours, h)));
+    hunks.extend(diff(&base, &theirs).into_iter().map(|h| (Side::Theirs, h)));
+    hunks.sort_by_key(|(_, h)| h.base_start);
+
+    let mut text = String::new();
+    let mut conflicts = 0;
+    let mut base_pos = 0;
+    let mut i = 0;
+    while i < hunks.len() {
+        // Gather every hunk that overlaps (or abuts) the region
+        // started by hunk `i`.
+        let start = hunks[i].1.base_start;
+        let mut end = hunks[i].1.base_end;
+        let mut j = i + 1;
+        while j < hunks.len() && hunks[j].1.base_start <= end {
+            if hunks[j].1.base_end > end {
+                end = hunks[j].1.base_end;
+            }
+            j += 1;
+        }
+        let region = &hunks[i..j];
+
+        push_lines(&mut text, &base[base_pos..start]);
+        let o = side_range(region, Side::Ours, start, end).map(|(lo, hi)| &ours[lo..hi]);
+        let t = side_range(region, Side::Theirs, start, end).map(|(lo, hi)| &theirs[lo..hi]);
+        match (o, t) {
+            (Some(o), None) => push_lines(&mut text, o),
+            (None, Some(t)) => push_lines(&mut text, t),
+            (Some(o), Some(t)) if o == t => push_lines(&mut text, o),
+            (Some(o), Some(t)) => {
+                conflicts += 1;
+                text.push_str(&format!("{} {}\n", CONFLICT_START, ours_label));
+                push_lines(&mut text, o);
+                text.push_str(&format!("{}\n", CONFLICT_SEP));
+                push_lines(&mut text, t);
+                text.push_str(&format!("{} {}\n", CONFLICT_END, theirs_label));
+            }
+            (None, None) => unreachable!("region must contain a hunk"),
+        }
+        base_pos = end;
+        i = j;
+    }
+    push_lines(&mut text, &base[base_pos..]);
+
+    Merged { text, conflicts }
+}
+
+fn push_lines(text: &mut String, lines: &[&str]) {
+    for l in lines {
+        text.push_str(l);
+        text.push('\n');
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Side { Ours, Theirs }
+
+// A maximal run of lines where `base[base_start..base_end]` was
+// replaced by `side[side_start..side_end]`.
+#[derive(Copy, Clone, Debug)]
+struct Hunk {
+    base_start: usize,
+    base_end: usize,
+    side_start: usize,
+    side_end: usize,
+}
+
+// Maps the base region `start..end` onto the lines of `side`, or
+// returns `None` if that side left the region untouched.
+fn side_range(region: &[(Side, Hunk)], side: Side, start: usize, end: usize)
+              -> Option<(usize, usize)> {
+    let mut hunks = region.iter().filter(|&&(s, _)| s == side).map(|&(_, h)| h);
+    let first = hunks.next()?;
+    let last = hunks.next_back().unwrap_or(first);
+    Some((first.side_start - (first.base_start - start),
+          last.side_end + (end - last.base_end)))
+}
+
+fn diff(base: &[&str], side: &[&str]) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    let (mut b, mut s) = (0, 0);
+    let matches = diff::common_lines(base, side);
+    for (mb, ms) in matches.into_iter().chain(Some((base.len(), side.len()))) {
+        if mb > b || ms > s {
+            hunks.push(Hunk { base_start: b, base_end: mb, side_start: s, side_end: ms });
+        }
+        b = mb + 1;
+        s = ms + 1;
+    }
+    hunks
+}
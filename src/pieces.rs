@@ -0,0 +1,67 @@
+// Code blocks written to other `.rs` files.
+//
+// A Rust block with a `file=PATH` attribute, such as
+// "```rust,file=src/parser/lexer.rs", has its code written to `PATH`
+// (relative to the project root) rather than to the `.md` file's own
+// twin. The twin keeps only the block's `//@@,file=...` meta note,
+// and in the other file the code of each such block (a "piece")
+// follows a marker line naming the `.md` file it came from:
+//
+//     //@from lit/design.md
+//     pub fn lex(input: &str) -> Vec<Token> {
+//         ...
+//     }
+//
+// Going back, rs2md fills in each block naming a file with the next
+// piece of that file. The twin and the files its notes name are
+// synced together, as a group, with the `.md` file.
+
+use info::Info;
+
+const PIECE_START: &str = "//@from ";
+
+/// The marker line that starts a piece taken from `origin`.
+pub fn start(origin: &str) -> String {
+    format!("{}{}", PIECE_START, origin)
+}
+
+/// Splits the lines of a file written from `file=` blocks into the
+/// code of each block. Lines ahead of the first marker are taken as
+/// part of the first block.
+pub fn split(lines: Vec<String>) -> Vec<Vec<String>> {
+    let mut pieces: Vec<Vec<String>> = Vec::new();
+    let mut leading = Vec::new();
+    for line in lines {
+        if line.starts_with(PIECE_START) {
+            let mut piece = Vec::new();
+            if pieces.is_empty() {
+                piece.append(&mut leading);
+            }
+            pieces.push(piece);
+        } else {
+            match pieces.last_mut() {
+                Some(piece) => piece.push(line),
+                None => leading.push(line),
+            }
+        }
+    }
+    pieces
+}
+
+/// The files named by `file=` attributes in the meta notes of the
+/// `.rs` text `text`, in order of first appearance.
+pub fn targets(text: &str) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let note = match line.trim_start().strip_prefix("//@@") {
+            Some(note) if !note.starts_with('@') => note.trim_end(),
+            _ => continue,
+        };
+        if let Some(file) = Info::from_note(note).file() {
+            if !files.iter().any(|f| f == file) {
+                files.push(file.to_string());
+            }
+        }
+    }
+    files
+}
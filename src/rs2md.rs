@@ -1,21 +1,87 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::io::{self, BufRead, Write};
-use super::encode_to_url;
+use std::mem;
+use std::path::PathBuf;
+use super::{encode_to_url, Config, Warning};
+use chapters;
+use chunks::{self, Untangler};
+use info::Info;
+use pieces;
 
 #[derive(Debug)]
 pub struct Converter {
+    config: Config,
     output_state: State,
     blank_line_count: usize,
     buffered_code: String,
     meta_note: Option<String>,
+    // The line and column of the meta note, for warnings.
+    meta_note_at: (usize, usize),
+    // How many of the blank lines pending came ahead of the meta note;
+    // those after it are the code block's own.
+    blank_lines_before_note: usize,
+    // Whether the code of the current block was commented out by
+    // md2rs, as it is not expected to compile.
+    commented: bool,
+    // Whether the next line of code follows a `//@#` line, and so is
+    // to be hidden as in rustdoc, with a "# " prefix.
+    hide_next: bool,
+    // The code of each chunk, as recovered from its expansions.
+    chunks: BTreeMap<String, Vec<String>>,
+    // The code of the blocks written to other files, by file, in the
+    // order the blocks appear.
+    pieces: BTreeMap<PathBuf, VecDeque<Vec<String>>>,
+    // The info string and lines of the code block being emitted, if
+    // any; the block is written out once complete, so that its fence
+    // can be made longer than any run of backticks inside it.
+    open_block: Option<(String, Vec<String>)>,
+    // Replaces the expansions of chunks in the lines fed, and the
+    // lines it is done with, by line number.
+    untangler: Untangler,
+    ready: Vec<(usize, String)>,
+    // The lines held back from the first definition of a chunk whose
+    // code is not yet known, as it may yet turn up in an expansion
+    // further on.
+    deferred: Option<Vec<(usize, String)>>,
+    // The number of the line being handled, and the column its text
+    // starts at, for warnings.
+    line: usize,
+    column: usize,
+    warnings: Vec<Warning>,
 }
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum State { MarkdownFirstLine, MarkdownLines, Rust, }
 impl Converter {
-    pub fn new() -> Converter {
-        Converter { output_state: State::MarkdownFirstLine,
+    pub fn new(config: &Config) -> Converter {
+        Converter { config: config.clone(),
+                    output_state: State::MarkdownFirstLine,
                     blank_line_count: 0,
                     buffered_code: String::new(),
-                    meta_note: None, }
+                    meta_note: None,
+                    meta_note_at: (0, 0),
+                    blank_lines_before_note: 0,
+                    commented: false,
+                    hide_next: false,
+                    chunks: BTreeMap::new(),
+                    pieces: BTreeMap::new(),
+                    open_block: None,
+                    untangler: Untangler::new(),
+                    ready: Vec::new(),
+                    deferred: None,
+                    line: 0,
+                    column: 0,
+                    warnings: Vec::new(), }
+    }
+
+    /// Supplies the content of `path`, which holds the code of blocks
+    /// with a `file=` attribute naming it.
+    pub(crate) fn add_pieces(&mut self, path: PathBuf, text: &str) {
+        let lines = text.lines().map(|line| line.trim_end_matches('\r').to_string()).collect();
+        let (lines, chunks) = chunks::untangle(lines);
+        for (name, body) in chunks {
+            self.chunks.entry(name).or_insert(body);
+        }
+        self.pieces.insert(path, pieces::split(lines).into_iter().collect());
     }
 }
 
@@ -25,45 +91,134 @@ enum Effect<'a> {
     WriteLn(&'a str),
     StartCodeBlock,
     FinisCodeBlock,
-    BlankLitComment,
 }
 
-#[derive(Debug)]
-enum EffectContext<'a> {
-    Finalize,
-    NonblankLine(&'a str),
-    Transition(State),
+
+impl super::Converter for Converter {
+    fn feed_line(&mut self, line: &str, w: &mut dyn Write) -> super::Result<()> {
+        let mut ready = mem::take(&mut self.ready);
+        // Carriage returns at the end are taken as part of the line
+        // ending, as md2rs takes them.
+        self.untangler.feed(line.trim_end_matches('\r').to_string(), &mut ready);
+        self.take_chunks();
+        for (number, line) in ready.drain(..) {
+            if self.deferred.is_none() && self.awaits_chunk(&line) {
+                self.deferred = Some(Vec::new());
+            }
+            match self.deferred {
+                Some(ref mut deferred) => deferred.push((number, line)),
+                None => (self.handle_at(number, &line, w))?,
+            }
+        }
+        self.ready = ready;
+        Ok(())
+    }
+
+    fn finish(&mut self, w: &mut dyn Write) -> super::Result<()> {
+        let mut rest = self.deferred.take().unwrap_or_default();
+        self.untangler.finish(&mut rest);
+        self.take_chunks();
+        for (number, line) in rest {
+            (self.handle_at(number, &line, w))?;
+        }
+        (self.finalize(w))?;
+        Ok(())
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
 }
 
 impl Converter {
-    pub fn convert<R:io::Read, W:io::Write>(&mut self, r:R, mut w:W) -> io::Result<()> {
+    // Whether `line` is the meta note of a chunk definition whose code
+    // is not known (yet).
+    fn awaits_chunk(&self, line: &str) -> bool {
+        match line.trim_start().strip_prefix("//@@") {
+            Some(note) if !note.starts_with('@') =>
+                Info::from_note(note.trim_end()).chunk().is_some_and(|name| !self.chunks.contains_key(name)),
+            _ => false,
+        }
+    }
+
+    fn take_chunks(&mut self) {
+        for (name, body) in self.untangler.take_chunks() {
+            self.chunks.entry(name).or_insert(body);
+        }
+        self.warnings.extend(self.untangler.take_warnings());
+    }
+
+    fn handle_at(&mut self, number: usize, line: &str, w: &mut dyn Write) -> io::Result<()> {
+        self.line = number;
+        self.column = line[..line.len() - line.trim_start().len()].chars().count() + 1;
+        self.handle(line, w)
+    }
+
+    /// Converts a `.rs` file assembled from chapters (see `chapters`),
+    /// returning the Markdown for each chapter by the name on its
+    /// marker line. Anything ahead of the first marker comes first,
+    /// with no name.
+    pub(crate) fn convert_chapters<R:io::Read>(&mut self, r:R) -> io::Result<Vec<(Option<String>, Vec<u8>)>> {
+        let mut parts = vec![(None, Vec::new())];
+        for (number, line) in (self.read_lines(r))? {
+            match chapters::name(&line) {
+                Some(name) => {
+                    (self.finalize(&mut parts.last_mut().unwrap().1))?;
+                    self.output_state = State::MarkdownFirstLine;
+                    self.blank_line_count = 0;
+                    self.hide_next = false;
+                    parts.push((Some(name.to_string()), Vec::new()));
+                }
+                None => (self.handle_at(number, &line, &mut parts.last_mut().unwrap().1))?,
+            }
+        }
+        (self.finalize(&mut parts.last_mut().unwrap().1))?;
+        Ok(parts)
+    }
+
+    // Reads the lines of `r` by line number, with each chunk expansion
+    // replaced by its reference.
+    fn read_lines<R:io::Read>(&mut self, r:R) -> io::Result<Vec<(usize, String)>> {
         let source = io::BufReader::new(r);
+        let mut lines = Vec::new();
         for line in source.lines() {
-            let line = (line)?;
-            (self.handle(&line, &mut w))?;
+            self.untangler.feed((line)?.trim_end_matches('\r').to_string(), &mut lines);
         }
-        self.finalize(&mut w)
+        self.untangler.finish(&mut lines);
+        self.take_chunks();
+        Ok(lines)
     }
 
-    pub fn finalize(&mut self, w: &mut dyn Write) -> io::Result<()> {
+    fn finalize(&mut self, w: &mut dyn Write) -> io::Result<()> {
         match self.output_state {
             State::Rust =>
-                self.effect(EffectContext::Finalize, Effect::FinisCodeBlock, w),
+                self.effect(Effect::FinisCodeBlock, w),
             State::MarkdownFirstLine |
-            State::MarkdownLines =>
+            State::MarkdownLines => {
+                self.drop_meta_note();
                 Ok(())
+            }
         }
     }
 
-    pub fn handle(&mut self, line: &str, w: &mut dyn Write) -> io::Result<()> {
+    fn handle(&mut self, line: &str, w: &mut dyn Write) -> io::Result<()> {
         let line_right = line.trim_start();
-        if line_right.is_empty() {
+        if line_right.is_empty() && self.hide_next {
+            if self.output_state != State::Rust {
+                (self.transition(w, State::Rust))?;
+            }
+            self.hidden_line("", w)
+        } else if line_right.is_empty() {
             self.blank_line(w)
+        } else if line_right == "//@#" {
+            self.hide_next = true;
+            Ok(())
         } else if line_right.starts_with("//@ ") {
+            // Only `//@ ` itself is a blank line: any whitespace after
+            // it is the text's own, e.g. a Markdown hard line break.
             let line = &line_right[4..];
-            if line.trim().is_empty() {
-                (self.blank_line(w))?
-            }
+            let blank = line.is_empty();
+            self.drop_meta_note();
             match self.output_state {
                 State::Rust =>
                     (self.transition(w, State::MarkdownFirstLine))?,
@@ -72,8 +227,8 @@ impl Converter {
                 State::MarkdownLines =>
                     {}
             }
-            if line.trim().is_empty() {
-                Ok(())
+            if blank {
+                self.blank_line(w)
             } else {
                 self.nonblank_line(line, w)
             }
@@ -96,13 +251,33 @@ impl Converter {
             }
             Ok(())
         } else if line_right.starts_with("//@@") {
+            // A meta note starts a code block of its own (even with no
+            // note, as md2rs marks a block that follows another), and
+            // the blank lines ahead of it are not the block's.
             let line = &line_right[4..];
-            if !line.trim().is_empty() {
-                self.set_meta_note(line.trim());
+            if self.output_state == State::Rust {
+                (self.transition(w, State::MarkdownFirstLine))?;
+            }
+            self.blank_lines_before_note = self.blank_line_count;
+            if line.trim().is_empty() {
+                self.set_meta_note("");
+            } else {
+                self.set_meta_note(line.trim_end());
+                let info = Info::from_note(line.trim_end());
+                let body = match (info.chunk(), info.file()) {
+                    (Some(name), _) if self.chunks.contains_key(name) => Some(self.chunks[name].clone()),
+                    (_, Some(file)) => self.pieces.get_mut(&self.config.resolve(file))
+                        .map(|pieces| pieces.pop_front().unwrap_or_default()),
+                    _ => None,
+                };
+                if let Some(body) = body {
+                    (self.emit_chunk(body, w))?;
+                }
             }
             Ok(())
         } else if line_right.starts_with("//@") {
             let line = &line_right[3..];
+            self.drop_meta_note();
             match self.output_state {
                 State::Rust =>
                     (self.transition(w, State::MarkdownFirstLine))?,
@@ -123,61 +298,138 @@ impl Converter {
                     (self.transition(w, State::Rust))?,
                 _ => {}
             }
-            self.nonblank_line(line, w)
+            let line = if self.commented && line.starts_with("// ") { &line[3..] } else { line };
+            if self.hide_next {
+                self.hidden_line(line, w)
+            } else if line == "#" || line.starts_with("# ") || line.starts_with("##") {
+                // Escaped as in rustdoc, so as not to be hidden.
+                self.shown_line(line, &format!("#{}", line), w)
+            } else {
+                self.nonblank_line(line, w)
+            }
+        }
+    }
+
+    // Emits the definition of a chunk whose code was taken from where
+    // it is referred to.
+    fn emit_chunk(&mut self, body: Vec<String>, w: &mut dyn Write) -> io::Result<()> {
+        if self.output_state == State::Rust {
+            (self.transition(w, State::MarkdownFirstLine))?;
+        }
+        (self.transition(w, State::Rust))?;
+        for line in body {
+            (self.handle(&line, w))?;
+        }
+        // The chunk's blank lines are its own, even at its end.
+        for _ in 0..mem::replace(&mut self.blank_line_count, 0) {
+            self.buffered_code.push('\n');
+            (self.effect(Effect::BlankLn, w))?;
         }
+        self.transition(w, State::MarkdownFirstLine)
     }
 
     fn emit_named_code(&mut self, name: &str, w: &mut dyn Write) -> io::Result<()> {
-        writeln!(w, "[{}]: {}", name, encode_to_url(&self.buffered_code))
+        for _ in 0..mem::replace(&mut self.blank_line_count, 0) {
+            (writeln!(w))?;
+        }
+        writeln!(w, "[{}]: {}", name, encode_to_url(&self.buffered_code, &self.config))
     }
 
     fn set_meta_note(&mut self, note: &str) {
-        if let Some(ref prev_note) = self.meta_note {
-            println!("warning: discarding meta note {} for {}", prev_note, note);
+        if let Some(prev_note) = self.meta_note.take() {
+            self.warnings.push(Warning::DiscardedMetaNote {
+                discarded: prev_note,
+                kept: note.to_string(),
+                line: self.line,
+                column: self.column,
+            });
         }
         self.meta_note = Some(note.to_string());
+        self.meta_note_at = (self.line, self.column);
     }
 
-    fn effect(&mut self, _c: EffectContext, e: Effect, w: &mut dyn Write) -> io::Result<()> {
-        // println!("effect _c: {:?} e: {:?}", _c, e);
+    // Drops the meta note pending, if any, as Markdown or the end of
+    // the file has come before the code it is for.
+    fn drop_meta_note(&mut self) {
+        if let Some(note) = self.meta_note.take() {
+            let (line, column) = self.meta_note_at;
+            self.warnings.push(Warning::OrphanedMetaNote { note, line, column });
+        }
+    }
+
+    fn effect(&mut self, e: Effect, w: &mut dyn Write) -> io::Result<()> {
+        // println!("effect e: {:?}", e);
         match e {
+            Effect::BlankLn | Effect::WriteLn(_) if self.open_block.is_some() => {
+                let line = match e { Effect::WriteLn(line) => line, _ => "" };
+                if let Some((_, ref mut lines)) = self.open_block {
+                    lines.push(line.to_string());
+                }
+                Ok(())
+            }
             Effect::BlankLn => writeln!(w, ""),
             Effect::WriteLn(line) => writeln!(w, "{}", line),
             Effect::StartCodeBlock => {
-                if let Some(ref note) = self.meta_note {
-                    assert_eq!(note.chars().next(), Some('{'));
-                    (writeln!(w, "```{{.rust{}", &note[1..]))?;
-                } else {
-                    (writeln!(w, "```rust"))?;
-                }
+                let lang = self.config.fence_languages().remove(0);
+                let info = match self.meta_note {
+                    Some(ref note) => Info::from_note(note),
+                    None => Info::default(),
+                };
+                self.commented = !info.compiles();
+                self.open_block = Some((info.render(&lang), Vec::new()));
                 self.meta_note = None;
                 self.buffered_code = String::new();
                 Ok(())
             }
             Effect::FinisCodeBlock => {
-                (writeln!(w, "```"))?;
+                if let Some((info, lines)) = self.open_block.take() {
+                    let fence = fence_for(&info, &lines);
+                    (writeln!(w, "{}{}", fence, info))?;
+                    for line in &lines {
+                        (writeln!(w, "{}", line))?;
+                    }
+                    (writeln!(w, "{}", fence))?;
+                }
                 Ok(())
             }
-            Effect::BlankLitComment => writeln!(w, ""),
         }
     }
 
     fn nonblank_line(&mut self, line: &str, w: &mut dyn Write) -> io::Result<()> {
         for _ in 0..self.blank_line_count {
-            (self.effect(EffectContext::NonblankLine(line), Effect::BlankLn, w))?;
+            if State::Rust == self.output_state {
+                self.buffered_code.push('\n');
+            }
+            (self.effect(Effect::BlankLn, w))?;
         }
         if State::Rust == self.output_state {
-            self.buffered_code = format!("{}\n{}", self.buffered_code, line);
+            self.buffered_code.push('\n');
+            self.buffered_code.push_str(line);
+        }
+        self.blank_line_count = 0;
+        self.effect(Effect::WriteLn(line), w)
+    }
+
+    fn hidden_line(&mut self, code: &str, w: &mut dyn Write) -> io::Result<()> {
+        self.hide_next = false;
+        let shown = if code.is_empty() { "#".to_string() } else { format!("# {}", code) };
+        self.shown_line(code, &shown, w)
+    }
+
+    // Writes a line of code in the form `shown`.
+    fn shown_line(&mut self, code: &str, shown: &str, w: &mut dyn Write) -> io::Result<()> {
+        for _ in 0..self.blank_line_count {
+            self.buffered_code.push('\n');
+            (self.effect(Effect::BlankLn, w))?;
         }
         self.blank_line_count = 0;
-        self.effect(EffectContext::NonblankLine(line), Effect::WriteLn(line), w)
+        self.buffered_code.push('\n');
+        self.buffered_code.push_str(code);
+        self.effect(Effect::WriteLn(shown), w)
     }
 
     fn blank_line(&mut self, _w: &mut dyn Write) -> io::Result<()> {
         self.blank_line_count += 1;
-        if State::Rust == self.output_state {
-            self.buffered_code = format!("{}\n", self.buffered_code);
-        }
         Ok(())
     }
 
@@ -185,34 +437,54 @@ impl Converter {
         Ok(())
     }
 
+    // Blank lines are left pending across transitions to Markdown, to
+    // be written ahead of whatever comes next, or dropped at the end of
+    // the file as md2rs drops them. Moving to the state already in is
+    // nothing.
     fn transition(&mut self, w: &mut dyn Write, s: State) -> io::Result<()> {
+        if s == self.output_state {
+            return Ok(());
+        }
         match s {
             State::MarkdownFirstLine => {
-                assert_eq!(self.output_state, State::Rust);
-                (self.effect(EffectContext::Transition(s), Effect::FinisCodeBlock, w))?;
-                for _ in 0..self.blank_line_count {
-                    (self.effect(EffectContext::Transition(s), Effect::BlankLn, w))?;
-                }
-                self.blank_line_count = 0;
-            }
-            State::MarkdownLines => {
-                assert_eq!(self.output_state, State::MarkdownFirstLine);
-                for _ in 0..self.blank_line_count {
-                    (self.effect(EffectContext::Transition(s), Effect::BlankLitComment, w))?;
-                }
-                self.blank_line_count = 0;
+                self.hide_next = false;
+                (self.effect(Effect::FinisCodeBlock, w))?;
             }
+            State::MarkdownLines => {}
             State::Rust => {
-                assert!(self.output_state != State::Rust);
                 (self.finish_section(w))?;
-                for _ in 0..self.blank_line_count {
-                    (self.effect(EffectContext::Transition(s), Effect::BlankLn, w))?;
+                // Blank lines after a meta note are the block's own;
+                // otherwise they come ahead of it.
+                let ahead = match self.meta_note {
+                    Some(_) => self.blank_lines_before_note.min(self.blank_line_count),
+                    None => self.blank_line_count,
+                };
+                for _ in 0..ahead {
+                    (self.effect(Effect::BlankLn, w))?;
+                }
+                (self.effect(Effect::StartCodeBlock, w))?;
+                for _ in ahead..self.blank_line_count {
+                    self.buffered_code.push('\n');
+                    (self.effect(Effect::BlankLn, w))?;
                 }
                 self.blank_line_count = 0;
-                (self.effect(EffectContext::Transition(s), Effect::StartCodeBlock, w))?;
             }
         }
         self.output_state = s;
         Ok(())
     }
 }
+
+// Returns a fence for a code block with `info` as its info string,
+// longer than any that could close the block early on one of
+// `lines`; usually just "```". A backtick fence cannot carry an info
+// string holding a backtick, so such a block is fenced with tildes.
+fn fence_for(info: &str, lines: &[String]) -> String {
+    let mark = if info.contains('`') { '~' } else { '`' };
+    let longest = lines.iter()
+        .map(|line| line.trim_start_matches(' '))
+        .map(|rest| rest.len() - rest.trim_start_matches(mark).len())
+        .max()
+        .unwrap_or(0);
+    mark.to_string().repeat(if longest < 3 { 3 } else { longest + 1 })
+}
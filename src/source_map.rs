@@ -0,0 +1,164 @@
+//! Where each line of a generated `.rs` file came from.
+//!
+//! Whenever tango generates a `.rs` file (or finds one without a map),
+//! it records a source map for it under `tango.map/`, e.g.
+//! `tango.map/src/foo.rs.map` for `src/foo.rs`. The map lists the
+//! `.md` files the `.rs` file was generated from (and the hash of
+//! their content, to tell when the map is out of date), and for each line of
+//! the `.rs` file, the `.md` line it came from along with the shift
+//! from the `.rs` column to the `.md` one (e.g. -4 for a text line,
+//! which gains a `//@ ` prefix):
+//!
+//! ```text
+//! # Generated by tango: ...
+//! hash    1f0e3dad99908345
+//! source  src/foo.md
+//! 1       0       1       -4
+//! 2       0       2       0
+//! ```
+//!
+//! Fields are separated by tabs. Lines are numbered from 1, and the
+//! second field is an index into the sources. Lines starting with `#`
+//! are comments.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use lock::ContentHash;
+
+/// The `.md` position a line of a `.rs` file came from.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Origin {
+    /// Index of the `.md` file among the sources of the map.
+    pub source: usize,
+    pub line: usize,
+    /// What to add to a column of the `.rs` line to get the column
+    /// of the `.md` line.
+    pub shift: isize,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SourceMap {
+    // The hash of the sources' content, as for the `.md` side of the
+    // pair in `tango.lock`.
+    md_hash: ContentHash,
+    sources: Vec<PathBuf>,
+    lines: Vec<Origin>,
+}
+
+const HEADER: &str = "\
+# Generated by tango: the .md line that each line of a .rs file came
+# from. Do not edit by hand.
+";
+
+fn invalid(line: usize, text: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("malformed source map entry on line {}: {:?}", line, text))
+}
+
+impl SourceMap {
+    pub fn new(md_hash: ContentHash, sources: Vec<PathBuf>, lines: Vec<Origin>) -> SourceMap {
+        SourceMap { md_hash, sources, lines }
+    }
+
+    pub fn md_hash(&self) -> ContentHash {
+        self.md_hash
+    }
+
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    pub fn lines(&self) -> &[Origin] {
+        &self.lines
+    }
+
+    pub fn read<P: AsRef<Path>>(p: P) -> io::Result<SourceMap> {
+        let mut s = String::new();
+        (File::open(p)?).read_to_string(&mut s)?;
+        SourceMap::parse(&s)
+    }
+
+    pub fn parse(s: &str) -> io::Result<SourceMap> {
+        let mut map = SourceMap::new(ContentHash(0), vec![], vec![]);
+        let mut hashed = false;
+        for (i, text) in s.lines().enumerate() {
+            if text.trim().is_empty() || text.starts_with('#') {
+                continue;
+            }
+            if let Some(hash) = text.strip_prefix("hash\t") {
+                match ContentHash::parse(hash) {
+                    Some(hash) if !hashed => map.md_hash = hash,
+                    _ => return Err(invalid(i + 1, text)),
+                }
+                hashed = true;
+                continue;
+            }
+            if let Some(source) = text.strip_prefix("source\t") {
+                map.sources.push(PathBuf::from(source));
+                continue;
+            }
+            let fields: Vec<&str> = text.split('\t').collect();
+            let origin = match fields[..] {
+                [line, source, md_line, shift] => {
+                    match (line.parse::<usize>(), source.parse(), md_line.parse(), shift.parse()) {
+                        (Ok(line), Ok(source), Ok(md_line), Ok(shift))
+                            if line == map.lines.len() + 1 && source < map.sources.len() =>
+                            Some(Origin { source, line: md_line, shift }),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match origin {
+                Some(origin) => map.lines.push(origin),
+                None => return Err(invalid(i + 1, text)),
+            }
+        }
+        if !hashed {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "source map records no hash"));
+        }
+        Ok(map)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, p: P) -> io::Result<()> {
+        let mut f = File::create(p)?;
+        self.write_to(&mut f)?;
+        f.flush()
+    }
+
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(HEADER.as_bytes())?;
+        writeln!(w, "hash\t{}", self.md_hash)?;
+        for source in &self.sources {
+            writeln!(w, "source\t{}", source.display())?;
+        }
+        for (i, o) in self.lines.iter().enumerate() {
+            writeln!(w, "{}\t{}\t{}\t{}", i + 1, o.source, o.line, o.shift)?;
+        }
+        Ok(())
+    }
+
+    /// Maps the given line and column of the `.rs` file to the `.md`
+    /// file, line and column they came from.
+    pub fn to_md(&self, line: usize, col: usize) -> Option<(&Path, usize, usize)> {
+        let o = (line.checked_sub(1).and_then(|i| self.lines.get(i)))?;
+        Some((&self.sources[o.source], o.line, shifted(col, o.shift)))
+    }
+
+    /// Maps the given line and column of the `.md` file `source` to
+    /// the first line of the `.rs` file generated from that line.
+    pub fn to_rs(&self, source: &Path, line: usize, col: usize) -> Option<(usize, usize)> {
+        let index = (self.sources.iter().position(|s| s == source))?;
+        self.lines.iter()
+            .position(|o| o.source == index && o.line == line)
+            .map(|i| (i + 1, shifted(col, -self.lines[i].shift)))
+    }
+}
+
+// Shifts a column, keeping it within the line.
+fn shifted(col: usize, shift: isize) -> usize {
+    let col = col as isize + shift;
+    if col < 1 { 1 } else { col as usize }
+}
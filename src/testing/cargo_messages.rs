@@ -0,0 +1,46 @@
+use cargo_messages;
+use serde_json::{self, Value};
+use std::path::{Path, PathBuf};
+
+const MESSAGE: &str = r#"{"reason":"compiler-message","package_id":"demo 0.1.0","message":{"message":"mismatched types","code":{"code":"E0308","explanation":null},"level":"error","spans":[{"file_name":"src/lib.rs","byte_start":90,"byte_end":91,"line_start":8,"line_end":8,"column_start":17,"column_end":18,"is_primary":true,"text":[],"label":"expected `u8`","expansion":null}],"children":[{"message":"consider","code":null,"level":"help","spans":[{"file_name":"src/other.rs","byte_start":3,"byte_end":4,"line_start":2,"line_end":2,"column_start":1,"column_end":2,"is_primary":true,"text":[],"label":null,"expansion":null}],"children":[],"rendered":null}],"rendered":"error[E0308]: mismatched types\n"}}"#;
+
+// Maps the lines of `src/lib.rs` to those of `src/lib.md` two lines
+// further on, with text four columns to the right.
+fn lookup(rs: &Path, line: usize, col: usize) -> Option<(PathBuf, usize, usize)> {
+    if rs == Path::new("src/lib.rs") && line < 10 {
+        Some((PathBuf::from("src/lib.md"), line + 2, col + 4))
+    } else {
+        None
+    }
+}
+
+fn rewrite(input: &str) -> String {
+    let mut out = Vec::new();
+    cargo_messages::rewrite(input.as_bytes(), &mut out, &mut lookup).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn spans_point_at_the_md_file() {
+    let out: Value = serde_json::from_str(&rewrite(MESSAGE)).unwrap();
+    let span = &out["message"]["spans"][0];
+    assert_eq!(span["file_name"], "src/lib.md");
+    assert_eq!((&span["line_start"], &span["line_end"]), (&Value::from(10), &Value::from(10)));
+    assert_eq!((&span["column_start"], &span["column_end"]), (&Value::from(21), &Value::from(22)));
+    // Spans in files without a map are left alone.
+    assert_eq!(out["message"]["children"][0]["spans"][0]["file_name"], "src/other.rs");
+}
+
+#[test]
+fn other_lines_pass_through_unchanged() {
+    let other = MESSAGE.replace("src/lib.rs", "src/main.rs");
+    let input = format!("{}\n{}\n{}\n",
+                        r#"{"reason":"build-finished", "success":true}"#, other, "Compiling demo");
+    assert_eq!(rewrite(&input), input);
+}
+
+#[test]
+fn spans_crossing_files_are_left_alone() {
+    let across = MESSAGE.replace(r#""line_end":8"#, r#""line_end":12"#);
+    assert_eq!(rewrite(&across), format!("{}\n", across));
+}
@@ -0,0 +1,66 @@
+use {Config, Warning};
+use {md2rs, rs2md};
+
+const INTRO_MD: &str = "\
+# Introduction
+
+```rust
+mod types;
+```
+";
+
+const TYPES_MD: &str = "\
+# Types
+
+```rust
+pub struct Token;
+```
+";
+
+const LIB_RS: &str = "\
+//@chapter lit/01-intro.md
+//@ # Introduction
+
+mod types;
+//@chapter lit/02-types.md
+//@ # Types
+
+pub struct Token;
+";
+
+fn chapters() -> Vec<(String, String)> {
+    vec![("lit/01-intro.md".to_string(), INTRO_MD.to_string()),
+         ("lit/02-types.md".to_string(), TYPES_MD.to_string())]
+}
+
+#[test]
+fn chapters_are_joined_with_markers() {
+    let mut out = Vec::new();
+    md2rs::Converter::new(&Config::new()).convert_chapters(&chapters(), &mut out).ok().unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), LIB_RS);
+}
+
+#[test]
+fn rs_is_split_back_into_chapters() {
+    let parts = rs2md::Converter::new(&Config::new()).convert_chapters(LIB_RS.as_bytes()).unwrap();
+    let parts: Vec<(Option<String>, String)> = parts.into_iter()
+        .map(|(name, out)| (name, String::from_utf8(out).unwrap()))
+        .collect();
+    let mut expect = vec![(None, String::new())];
+    expect.extend(chapters().into_iter().map(|(name, md)| (Some(name), md)));
+    assert_eq!(parts, expect);
+}
+
+#[test]
+fn open_fence_ends_with_its_chapter() {
+    let chapters = vec![("a.md".to_string(), "```rust\nfn a() {}\n".to_string()),
+                        ("b.md".to_string(), "b\n".to_string())];
+    let mut out = Vec::new();
+    // The fence is warned of, in the chapter it is in.
+    match md2rs::Converter::new(&Config::new()).convert_chapters(&chapters, &mut out) {
+        Err(md2rs::Exception::Warnings(ref warnings))
+            if *warnings == [(0, Warning::UnclosedFence { line: 1, column: 1 })] => {}
+        _ => panic!("expected a warning of the open fence"),
+    }
+    assert_eq!(String::from_utf8(out).unwrap(), "//@chapter a.md\nfn a() {}\n//@chapter b.md\n//@ b\n");
+}
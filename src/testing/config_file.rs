@@ -0,0 +1,161 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use config_file::{apply, apply_cargo_metadata};
+use {Config, Error, MdPath, RsPath, SyncMode, CONFIG_FILE};
+use {encode_to_url, md2rs_with_config, rs2md_with_config};
+
+#[test]
+fn files_section_sets_rules_and_mappings() {
+    let mut c = Config::new();
+    apply(&mut c, r#"
+[files]
+include = ["src/**"]
+exclude = ["src/generated/**"]
+
+[files.map]
+"src/lib.rs" = "README.md"
+"#).unwrap();
+    assert!(c.selects(Path::new("src/a.rs"), Path::new("src/a.md")));
+    assert!(!c.selects(Path::new("src/generated/b.rs"), Path::new("src/generated/b.md")));
+    let rs = RsPath::new(Path::new("src/lib.rs").to_path_buf(), &c).unwrap();
+    assert_eq!(rs.to_md(&c).unwrap().0, Path::new("README.md"));
+}
+
+#[test]
+fn chapters_pair_with_their_rs_file() {
+    let mut c = Config::new();
+    apply(&mut c, r#"
+[files.chapters]
+"src/lib.rs" = ["lit/01-intro.md", "lit/02-types.md"]
+"#).unwrap();
+    let md = RsPath::new(Path::new("src/lib.rs").to_path_buf(), &c).unwrap().to_md(&c).unwrap();
+    assert_eq!(md.0, Path::new("lit/01-intro.md"));
+    assert_eq!(md.1, vec![Path::new("lit/02-types.md").to_path_buf()]);
+    let md = MdPath::new(Path::new("lit/02-types.md").to_path_buf(), &c).unwrap();
+    assert_eq!(md.to_rs(&c).unwrap().0, Path::new("src/lib.rs"));
+}
+
+#[test]
+fn missing_files_section_is_fine() {
+    apply(&mut Config::new(), "").unwrap();
+}
+
+#[test]
+fn malformed_files_section_is_an_error() {
+    for text in &["[files]\ninclude = \"src/**\"\n",
+                  "[files]\nexclude = [1]\n",
+                  "[files]\nmap = [\"README.md\"]\n",
+                  "[files.chapters]\n\"src/lib.rs\" = []\n",
+                  "[files\n"] {
+        match apply(&mut Config::new(), text) {
+            Err(Error::ConfigError(_)) => {}
+            _ => panic!("expected a configuration error for {:?}", text),
+        }
+    }
+}
+
+#[test]
+fn top_level_settings() {
+    let mut c = Config::new();
+    apply(&mut c, r#"
+src-dir = "code"
+lit-dir = "doc"
+emit-rerun-if = true
+sync = "mtime"
+fence-languages = ["rs", "rust"]
+
+[playground]
+url = "https://example.com/play"
+channel = "stable"
+check-urls = false
+"#).unwrap();
+    assert_eq!(c.src_dir(), "code");
+    assert_eq!(c.lit_dir(), "doc");
+    assert!(c.rerun_if());
+    assert_eq!(c.sync_mode(), SyncMode::Mtime);
+    assert_eq!(c.fence_languages(), vec!["rs", "rust"]);
+    assert_eq!(c.playground_url(), "https://example.com/play");
+    assert_eq!(c.playground_channel(), "stable");
+    assert!(!c.check_urls());
+}
+
+#[test]
+fn cargo_metadata_settings() {
+    let mut c = Config::new();
+    apply_cargo_metadata(&mut c, r#"
+[package]
+name = "demo"
+version = "0.1.0"
+
+[package.metadata.tango]
+lit-dir = "doc"
+
+[package.metadata.tango.files]
+exclude = ["src/main.rs"]
+"#).unwrap();
+    assert_eq!(c.lit_dir(), "doc");
+    assert!(!c.selects(Path::new("src/main.rs"), Path::new("doc/main.md")));
+
+    // A manifest without tango metadata changes nothing.
+    let mut c = Config::new();
+    apply_cargo_metadata(&mut c, "[package]\nname = \"demo\"\n").unwrap();
+    assert_eq!(c.lit_dir(), ::DEFAULT_LIT_DIR);
+}
+
+#[test]
+fn invalid_settings_are_errors() {
+    for text in &["sync = \"sometimes\"\n",
+                  "fence-languages = []\n",
+                  "emit-rerun-if = \"yes\"\n",
+                  "[playground]\nversion = \"nightly\"\n",
+                  "no-such-setting = 1\n"] {
+        match apply(&mut Config::new(), text) {
+            Err(Error::ConfigError(_)) => {}
+            _ => panic!("expected a configuration error for {:?}", text),
+        }
+    }
+}
+
+#[test]
+fn builder_settings_take_precedence_over_files() {
+    let dir = env::temp_dir().join(format!("tango-config-precedence-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("Cargo.toml"), r#"
+[package.metadata.tango]
+src-dir = "from-cargo"
+lit-dir = "from-cargo"
+"#).unwrap();
+    fs::write(dir.join(CONFIG_FILE), "lit-dir = \"from-tango\"\nsync = \"mtime\"\n").unwrap();
+
+    let mut c = Config::new();
+    c.set_root(&dir).set_sync_mode(SyncMode::ContentHash);
+    let c = c.load_files().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(c.src_dir(), "from-cargo");
+    assert_eq!(c.lit_dir(), "from-tango");
+    assert_eq!(c.sync_mode(), SyncMode::ContentHash);
+}
+
+#[test]
+fn fence_languages_and_playground_drive_conversion() {
+    let mut c = Config::new();
+    c.set_fence_languages(vec!["rs".to_string(), "rust".to_string()])
+        .set_playground_url("https://example.com/play".to_string())
+        .set_playground_channel("stable".to_string());
+
+    let mut rs = Vec::new();
+    md2rs_with_config(&c, "```rust\nfn a() {}\n```\n```rs\nfn b() {}\n```\n".as_bytes(),
+                      &mut rs).unwrap();
+    assert_eq!(String::from_utf8(rs.clone()).unwrap(), "fn a() {}\n//@@\nfn b() {}\n");
+
+    let mut md = Vec::new();
+    rs2md_with_config(&c, &rs[..], &mut md).unwrap();
+    assert_eq!(String::from_utf8(md).unwrap(), "```rs\nfn a() {}\n```\n```rs\nfn b() {}\n```\n");
+
+    let url = encode_to_url("fn a() {}", &c);
+    assert!(url.starts_with("https://example.com/play?code="));
+    assert!(url.ends_with("&version=stable"));
+}
@@ -0,0 +1,165 @@
+use std::io::Cursor;
+
+use quickcheck::TestResult;
+
+use super::roundtrip::{check, Picks, MD_LINES};
+use super::test_snippets::{HELLO9_LINK_TO_PLAY_MD_WARN, HELLO9_LINK_TO_PLAY_RS};
+use md2rs::Exception;
+use {md_to_rs, rs_to_md, Config, Converter, MdToRs, RsToMd, Warning};
+
+const SETUP_FIRST_MD: &str = "\
+```rust <<setup>>=
+let x = 1;
+```
+
+```rust
+fn main() {
+    <<setup>>
+}
+```
+";
+
+const SETUP_FIRST_RS: &str = "\
+//@@ <<setup>>=
+
+fn main() {
+    //@<< setup
+    let x = 1;
+    //@>>
+}
+";
+
+fn md2rs(text: &str) -> String {
+    let mut out = Vec::new();
+    MdToRs::new(&Config::new()).convert_str(text, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn rs2md(text: &str) -> String {
+    let mut out = Vec::new();
+    RsToMd::new(&Config::new()).convert_str(text, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// Checks that `input` converts alike from a string, a reader and an
+// iterator of lines.
+fn check_inputs_agree<C: Converter, F: Fn() -> C>(new: F, input: &str) {
+    let (mut from_str, mut from_reader, mut from_lines) = (Vec::new(), Vec::new(), Vec::new());
+    let converter: &mut dyn Converter = &mut new();
+    converter.convert_str(input, &mut from_str).unwrap();
+    new().convert_reader(Cursor::new(input), &mut from_reader).unwrap();
+    new().convert_lines(input.lines(), &mut from_lines).unwrap();
+    assert_eq!(String::from_utf8_lossy(&from_reader), String::from_utf8_lossy(&from_str));
+    assert_eq!(String::from_utf8_lossy(&from_lines), String::from_utf8_lossy(&from_str));
+}
+
+#[test]
+fn every_kind_of_input_converts_alike() {
+    let config = Config::new();
+    check_inputs_agree(|| MdToRs::new(&config), SETUP_FIRST_MD);
+    check_inputs_agree(|| RsToMd::new(&config), SETUP_FIRST_RS);
+}
+
+#[test]
+fn rs2md_writes_as_it_goes() {
+    let mut rs2md = RsToMd::new(&Config::new());
+    let mut out = Vec::new();
+    for line in &["//@ # Title", "//@", "//@ Some text.", "fn main() {}"] {
+        rs2md.feed_line(line, &mut out).unwrap();
+    }
+    // The code block is written once it is complete.
+    assert_eq!(String::from_utf8_lossy(&out), "# Title\n\nSome text.\n");
+    rs2md.feed_line("//@ More text.", &mut out).unwrap();
+    assert!(String::from_utf8_lossy(&out).ends_with("```rust\nfn main() {}\n```\nMore text.\n"));
+    rs2md.finish(&mut out).unwrap();
+}
+
+#[test]
+fn md2rs_writes_each_block_once_it_closes() {
+    let mut md2rs = MdToRs::new(&Config::new());
+    let mut out = Vec::new();
+    for line in &["# Title", "", "```rust", "fn main() {}"] {
+        md2rs.feed_line(line, &mut out).unwrap();
+    }
+    assert_eq!(String::from_utf8_lossy(&out), "");
+    md2rs.feed_line("```", &mut out).unwrap();
+    assert_eq!(String::from_utf8_lossy(&out), "//@ # Title\n\nfn main() {}\n");
+    // A fence left open runs to the end of the input.
+    for line in &["Some text.", "```rust", "fn f() {}"] {
+        md2rs.feed_line(line, &mut out).unwrap();
+    }
+    assert_eq!(String::from_utf8_lossy(&out), "//@ # Title\n\nfn main() {}\n");
+    md2rs.finish(&mut out).unwrap();
+    assert!(String::from_utf8_lossy(&out).ends_with("fn main() {}\n//@ Some text.\nfn f() {}\n"));
+    assert_eq!(md2rs.take_warnings(), vec![Warning::UnclosedFence { line: 7, column: 1 }]);
+}
+
+// Converting block by block gives what converting the whole document
+// at once does.
+#[test]
+fn md2rs_streams_as_it_converts_whole_documents() {
+    fn prop(picks: Picks) -> TestResult {
+        // (The warnings of each part converted come in their own
+        // order, so are compared by place.)
+        fn sorted(mut warnings: Vec<Warning>) -> Vec<Warning> {
+            warnings.sort_by_key(|w| (w.line(), w.column(), w.code()));
+            warnings
+        }
+        let md = picks.text(MD_LINES);
+        let mut whole = Vec::new();
+        let same = match (md_to_rs(&md), MdToRs::new(&Config::new()).convert_all(md.as_bytes(), &mut whole)) {
+            (Ok(streamed), Ok(_)) => streamed.warnings.is_empty() && streamed.output.as_bytes() == &whole[..],
+            (Ok(streamed), Err(Exception::Warnings(warnings))) =>
+                streamed.output.as_bytes() == &whole[..]
+                    && sorted(streamed.warnings) == sorted(warnings.into_iter().map(|(_, w)| w).collect()),
+            (Err(_), Err(Exception::Chunks(..))) => true,
+            _ => false,
+        };
+        TestResult::from_bool(same)
+    }
+    check(2000, prop);
+}
+
+#[test]
+fn md2rs_holds_documents_with_chunks_to_the_end() {
+    let mut md2rs = MdToRs::new(&Config::new());
+    let mut out = Vec::new();
+    for line in SETUP_FIRST_MD.lines() {
+        md2rs.feed_line(line, &mut out).unwrap();
+    }
+    assert_eq!(String::from_utf8_lossy(&out), "");
+    md2rs.finish(&mut out).unwrap();
+    assert_eq!(String::from_utf8_lossy(&out), SETUP_FIRST_RS);
+}
+
+#[test]
+fn chunks_defined_before_their_expansion_round_trip() {
+    assert_eq!(md2rs(SETUP_FIRST_MD), SETUP_FIRST_RS);
+    assert_eq!(rs2md(SETUP_FIRST_RS), SETUP_FIRST_MD);
+}
+
+#[test]
+fn in_memory_conversion_returns_warnings_with_the_output() {
+    let converted = md_to_rs(HELLO9_LINK_TO_PLAY_MD_WARN).unwrap();
+    assert_eq!(converted.output, HELLO9_LINK_TO_PLAY_RS);
+    match converted.warnings[..] {
+        [Warning::EncodedUrlMismatch { ref actual, line, .. }] => {
+            assert_eq!(line, 7);
+            assert!(actual.contains("code=does_not_match"), "{}", actual);
+        }
+        ref other => panic!("unexpected warnings: {:?}", other),
+    }
+    assert_eq!(md_to_rs(SETUP_FIRST_MD).unwrap().warnings, vec![]);
+}
+
+#[test]
+fn rs_to_md_warns_of_discarded_meta_notes() {
+    let converted = rs_to_md("//@@,ignore\n//@@,no_run\nfn main() {}\n").unwrap();
+    assert_eq!(converted.output, "```rust,no_run\nfn main() {}\n```\n");
+    assert_eq!(converted.warnings, vec![Warning::DiscardedMetaNote {
+        discarded: ",ignore".to_string(),
+        kept: ",no_run".to_string(),
+        line: 2,
+        column: 1,
+    }]);
+}
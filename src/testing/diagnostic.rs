@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use {md_to_rs, rs_to_md, Diagnostic, Severity, Warning};
+
+#[test]
+fn diagnostics_render_like_rustc() {
+    let d = Diagnostic::new(Severity::Warning, "unclosed-fence", "code block is never closed".to_string(), 10, 3);
+    assert_eq!(d.to_string(), "warning[unclosed-fence]: code block is never closed\n  --> 10:3");
+    let d = d.in_file(Path::new("src/lib.md"), &("x\n".repeat(9) + "\t ```rust\n"));
+    assert_eq!(d.to_string(), "\
+warning[unclosed-fence]: code block is never closed
+  --> src/lib.md:10:3
+   |
+10 |      ```rust
+   |      ^^^^^^^");
+}
+
+#[test]
+fn unclosed_fences_are_warned_of() {
+    let converted = md_to_rs("Text\n\n  ```rust\nfn main() {}\n").unwrap();
+    assert_eq!(converted.output, "//@ Text\n\nfn main() {}\n");
+    assert_eq!(converted.warnings, vec![Warning::UnclosedFence { line: 3, column: 3 }]);
+    assert_eq!(converted.warnings[0].code(), "unclosed-fence");
+}
+
+#[test]
+fn orphaned_meta_notes_are_warned_of() {
+    let converted = rs_to_md("//@ Text\n  //@@,ignore\n//@ More\nfn main() {}\n//@@,no_run\n").unwrap();
+    assert_eq!(converted.output, "Text\nMore\n```rust\nfn main() {}\n```\n");
+    assert_eq!(converted.warnings, vec![
+        Warning::OrphanedMetaNote { note: ",ignore".to_string(), line: 2, column: 3 },
+        Warning::OrphanedMetaNote { note: ",no_run".to_string(), line: 5, column: 1 },
+    ]);
+}
+
+#[test]
+fn chunk_errors_point_at_the_reference() {
+    let md = "```rust <<a>>=\n```\n\n```rust\nfn main() {\n    <<b>>\n}\n```\n";
+    let d = match md_to_rs(md) {
+        Err(::Error::ChunkError(e)) => e.diagnostic(),
+        _ => panic!("expected a chunk error"),
+    };
+    assert_eq!((d.severity, d.code, d.line, d.column), (Severity::Error, "undefined-chunk", 6, 5));
+}
+
+#[test]
+fn url_mismatches_point_at_the_url() {
+    let md = "```rust\nfn main() {}\n```\n[run]: https://play.rust-lang.org/?code=x\n";
+    let warnings = md_to_rs(md).unwrap().warnings;
+    assert_eq!((warnings[0].code(), warnings[0].line(), warnings[0].column()), ("url-mismatch", 4, 8));
+}
@@ -0,0 +1,96 @@
+use quickcheck::quickcheck;
+use diff::{common_lines, unified};
+
+#[test]
+fn unified_is_empty_for_equal_inputs() {
+    assert_eq!(unified("a", "x\ny\n", "b", "x\ny\n"), "");
+}
+
+#[test]
+fn unified_reports_a_change_with_context() {
+    let a = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+    let b = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+    assert_eq!(unified("old", a, "new", b), "\
+--- old
++++ new
+@@ -2,7 +2,7 @@
+ 2
+ 3
+ 4
+-5
++five
+ 6
+ 7
+ 8
+");
+}
+
+#[test]
+fn unified_splits_distant_changes_into_hunks() {
+    let a = "a\n1\n2\n3\n4\n5\n6\n7\n8\nb\n";
+    let b = "A\n1\n2\n3\n4\n5\n6\n7\n8\nB\n";
+    assert_eq!(unified("old", a, "new", b), "\
+--- old
++++ new
+@@ -1,4 +1,4 @@
+-a
++A
+ 1
+ 2
+ 3
+@@ -7,4 +7,4 @@
+ 6
+ 7
+ 8
+-b
++B
+");
+}
+
+#[test]
+fn unified_handles_empty_side() {
+    assert_eq!(unified("old", "", "new", "x\n"), "\
+--- old
++++ new
+@@ -0,0 +1,1 @@
++x
+");
+}
+
+#[test]
+fn large_inputs_that_differ_throughout() {
+    let a: Vec<String> = (0..4000).map(|i| format!("a{}", i)).collect();
+    let b: Vec<String> = (0..4000).map(|i| format!("b{}", i)).collect();
+    let a: Vec<&str> = a.iter().map(|l| &l[..]).collect();
+    let b: Vec<&str> = b.iter().map(|l| &l[..]).collect();
+    assert_eq!(common_lines(&a, &b), vec![]);
+    let c: Vec<&str> = a.iter().enumerate().map(|(i, l)| if i % 3 == 0 { b[i] } else { l }).collect();
+    assert_eq!(common_lines(&a, &c).len(), 4000 - 1334);
+}
+
+#[test]
+fn common_lines_are_a_longest_common_subsequence() {
+    fn prop(a: Vec<u8>, b: Vec<u8>) -> bool {
+        // Few distinct lines, so that there is much in common.
+        let line = |c: &u8| ["x", "y", "z"][*c as usize % 3];
+        let a: Vec<&str> = a.iter().map(line).collect();
+        let b: Vec<&str> = b.iter().map(line).collect();
+        let pairs = common_lines(&a, &b);
+        let increasing = pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1);
+        let matching = pairs.iter().all(|&(i, j)| a[i] == b[j]);
+        // The length of a longest common subsequence, by dynamic
+        // programming.
+        let mut lengths = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 0..a.len() {
+            for j in 0..b.len() {
+                lengths[i + 1][j + 1] = if a[i] == b[j] {
+                    lengths[i][j] + 1
+                } else {
+                    ::std::cmp::max(lengths[i][j + 1], lengths[i + 1][j])
+                };
+            }
+        }
+        increasing && matching && pairs.len() == lengths[a.len()][b.len()]
+    }
+    quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+}
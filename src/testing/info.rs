@@ -0,0 +1,60 @@
+use info::Info;
+
+fn rust() -> Vec<String> {
+    vec!["rust".to_string()]
+}
+
+#[test]
+fn parses_rustdoc_attributes() {
+    let info = Info::parse("rust,no_run,edition2021", &rust()).unwrap();
+    assert_eq!(info.attributes(), vec!["no_run", "edition2021"]);
+    assert!(info.compiles());
+    assert!(!Info::parse("rust,ignore", &rust()).unwrap().compiles());
+    assert!(!Info::parse("rust, compile_fail", &rust()).unwrap().compiles());
+}
+
+#[test]
+fn parses_pandoc_attributes() {
+    let info = Info::parse("{.rust .numberLines .ignore startFrom=3}", &rust()).unwrap();
+    assert_eq!(info.attributes(), vec!["numberLines", "ignore", "startFrom=3"]);
+    assert!(!info.compiles());
+}
+
+#[test]
+fn other_languages_are_not_rust() {
+    for info in &["rusty", "toml", "", "{.rustic}", "{.toml .rust}"] {
+        assert_eq!(Info::parse(info, &rust()), None, "{:?}", info);
+    }
+}
+
+#[test]
+fn note_round_trips() {
+    for text in &["rust", "rust,ignore", "rust ignore", "rust,no_run,edition2021",
+                  "{.rust}", "{.rust .numberLines }", "{.rust #id key=\"a b\"}",
+                  // Rustdoc-style strings that look like pandoc ones,
+                  // or like other lines, once in a note.
+                  "rust {.numberLines}", "rust{x}", "rust\\x", "rust\\ {x}", "rust@x"] {
+        let info = Info::parse(text, &rust()).unwrap();
+        let back = match info.note() {
+            Some(note) => Info::from_note(&note),
+            None => Info::default(),
+        };
+        assert_eq!(back.render("rust"), *text);
+    }
+}
+
+#[test]
+fn parses_chunk_definitions() {
+    let info = Info::parse("rust,no_run <<parse header>>=", &rust()).unwrap();
+    assert_eq!(info.chunk(), Some("parse header"));
+    assert_eq!(info.attributes(), vec!["no_run"]);
+    assert_eq!(Info::parse("rust <<>>=", &rust()).unwrap().chunk(), None);
+    assert_eq!(Info::parse("rust <<a>>", &rust()).unwrap().chunk(), None);
+}
+
+#[test]
+fn parses_target_files() {
+    assert_eq!(Info::parse("rust,file=src/lexer.rs", &rust()).unwrap().file(), Some("src/lexer.rs"));
+    assert_eq!(Info::parse("{.rust file=src/a.rs}", &rust()).unwrap().file(), Some("src/a.rs"));
+    assert_eq!(Info::parse("rust,file=", &rust()).unwrap().file(), None);
+}
@@ -0,0 +1,54 @@
+use lock::{ContentHash, Entry, Lock};
+use std::path::{Path, PathBuf};
+
+fn entry(rs: &str, rs_hash: u64, md: &str, md_hash: u64) -> Entry {
+    Entry { rs_path: PathBuf::from(rs), rs_hash: ContentHash(rs_hash),
+            md_path: PathBuf::from(md), md_hash: ContentHash(md_hash), pieces: vec![] }
+}
+
+#[test]
+fn hash_distinguishes_content() {
+    assert_eq!(ContentHash::of_bytes(b"fn main() {}"), ContentHash::of_bytes(b"fn main() {}"));
+    assert!(ContentHash::of_bytes(b"fn main() {}") != ContentHash::of_bytes(b"fn main() { }"));
+}
+
+#[test]
+fn lock_round_trips_through_text() {
+    let mut lock = Lock::new();
+    lock.record(entry("src/lib.rs", 1, "src/lib.md", 2));
+    lock.record(entry("src/a.rs", 3, "src/a.md", 4));
+    let mut text = Vec::new();
+    lock.write_to(&mut text).unwrap();
+    let parsed = Lock::parse(&String::from_utf8(text).unwrap()).unwrap();
+    assert_eq!(parsed, lock);
+    assert_eq!(parsed.entries()[0].rs_path, Path::new("src/a.rs"));
+}
+
+#[test]
+fn lock_records_pieces() {
+    let mut lock = Lock::new();
+    let mut e = entry("src/lib.rs", 1, "src/lib.md", 2);
+    e.pieces = vec![PathBuf::from("src/parser/lexer.rs"), PathBuf::from("src/ast.rs")];
+    lock.record(e.clone());
+    let mut text = Vec::new();
+    lock.write_to(&mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains("src/lib.md\tsrc/parser/lexer.rs\tsrc/ast.rs\n"), "{}", text);
+    assert_eq!(Lock::parse(&text).unwrap().entries(), &[e][..]);
+}
+
+#[test]
+fn lookup_orients_hashes_by_direction() {
+    let mut lock = Lock::new();
+    lock.record(entry("src/lib.rs", 1, "src/lib.md", 2));
+    let rs = Path::new("src/lib.rs");
+    let md = Path::new("src/lib.md");
+    assert_eq!(lock.lookup(rs, md), Some((ContentHash(1), ContentHash(2))));
+    assert_eq!(lock.lookup(md, rs), Some((ContentHash(2), ContentHash(1))));
+    assert_eq!(lock.lookup(rs, Path::new("src/other.md")), None);
+}
+
+#[test]
+fn malformed_lock_is_rejected() {
+    assert!(Lock::parse("not a lock entry\n").is_err());
+}
@@ -0,0 +1,59 @@
+use quickcheck::TestResult;
+use super::roundtrip::{check, Picks, MD_LINES, RS_LINES};
+use {md_to_rs, rs_to_md, Warning};
+
+// Each converter takes anything, as one kind of file or the other,
+// warning only of lines that are there.
+#[test]
+fn any_input_converts() {
+    fn prop(picks: Picks) -> TestResult {
+        let lines: Vec<&str> = MD_LINES.iter().chain(RS_LINES).cloned().collect();
+        let text = picks.text(&lines);
+        let count = text.lines().count();
+        let mut warnings = rs_to_md(&text).unwrap().warnings;
+        if let Ok(rs) = md_to_rs(&text) {
+            warnings.extend(rs.warnings);
+        }
+        TestResult::from_bool(warnings.iter().all(|w| 1 <= w.line() && w.line() <= count))
+    }
+    check(2000, prop);
+}
+
+#[test]
+fn links_are_found_only_in_order() {
+    let md = "x]: https://play.rust-lang.org/?code=a [y\n";
+    assert_eq!(md_to_rs(md).unwrap().output, "//@ x]: https://play.rust-lang.org/?code=a [y\n");
+}
+
+#[test]
+fn links_with_no_name_are_text() {
+    let md = "[]: https://play.rust-lang.org/?code=x\n";
+    assert_eq!(md_to_rs(md).unwrap().output, "//@ []: https://play.rust-lang.org/?code=x\n");
+}
+
+#[test]
+fn tango_comments_in_expansions_are_left_out() {
+    let converted = rs_to_md("\
+//@@ <<a>>=
+
+fn main() {
+    //@<< a
+    //@@ <<a>>=
+    let x = 1;
+    //@ text
+    //@>>
+}
+").unwrap();
+    assert_eq!(converted.output, "```rust <<a>>=\nlet x = 1;\n```\n\n```rust\nfn main() {\n    <<a>>\n}\n```\n");
+    assert_eq!(converted.warnings, vec![
+        Warning::CommentInExpansion { comment: "//@@ <<a>>=".to_string(), line: 5, column: 5 },
+        Warning::CommentInExpansion { comment: "//@ text".to_string(), line: 7, column: 5 },
+    ]);
+}
+
+#[test]
+fn unclosed_expansions_are_taken_as_they_are() {
+    let converted = rs_to_md("fn main() {\n    //@<< a\n    let x = 1;\n}\n").unwrap();
+    assert_eq!(converted.warnings, vec![Warning::UnclosedExpansion { name: "a".to_string(), line: 2, column: 5 }]);
+    assert!(converted.output.contains("    let x = 1;\n}\n"), "{}", converted.output);
+}
@@ -0,0 +1,55 @@
+use merge::merge3;
+
+#[test]
+fn merge_takes_changes_from_each_side() {
+    let base = "a\nb\nc\nd\n";
+    let ours = "A\nb\nc\nd\n";
+    let theirs = "a\nb\nc\nD\n";
+    let m = merge3(base, ours, theirs, "ours", "theirs");
+    assert_eq!(m.conflicts, 0);
+    assert_eq!(m.text, "A\nb\nc\nD\n");
+}
+
+#[test]
+fn merge_accepts_identical_changes() {
+    let base = "a\nb\nc\n";
+    let both = "a\nB\nc\nd\n";
+    let m = merge3(base, both, both, "ours", "theirs");
+    assert_eq!(m.conflicts, 0);
+    assert_eq!(m.text, both);
+}
+
+#[test]
+fn merge_handles_insertions_and_deletions() {
+    let base = "a\nb\nc\nd\ne\n";
+    let ours = "a\nnew\nb\nc\nd\ne\n";
+    let theirs = "a\nb\nc\ne\n";
+    let m = merge3(base, ours, theirs, "ours", "theirs");
+    assert_eq!(m.conflicts, 0);
+    assert_eq!(m.text, "a\nnew\nb\nc\ne\n");
+}
+
+#[test]
+fn merge_marks_conflicting_changes() {
+    let base = "a\nb\nc\n";
+    let ours = "a\nours\nc\n";
+    let theirs = "a\ntheirs\nc\n";
+    let m = merge3(base, ours, theirs, "lib.md", "lib.rs");
+    assert_eq!(m.conflicts, 1);
+    assert_eq!(m.text, "a\n<<<<<<< lib.md\nours\n=======\ntheirs\n>>>>>>> lib.rs\nc\n");
+}
+
+#[test]
+fn conflict_markers_survive_round_trip() {
+    let base = "//@ # Title\n//@ Some text.\n\nfn main() { }\n";
+    let ours = "//@ # Title\n//@ Edited text.\n\nfn main() { }\n";
+    let theirs = "//@ # Title\n//@ Other text.\n\nfn main() { }\n";
+    let m = merge3(base, ours, theirs, "src/lib.md", "src/lib.rs");
+    assert_eq!(m.conflicts, 1);
+
+    let mut md = Vec::new();
+    ::rs2md(m.text.as_bytes(), &mut md).unwrap();
+    let mut rs = Vec::new();
+    ::md2rs(&md[..], &mut rs).unwrap();
+    assert_eq!(String::from_utf8(rs).unwrap(), m.text);
+}
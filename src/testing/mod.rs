@@ -1,5 +1,19 @@
 use super::{md2rs, rs2md};
 mod test_snippets;
+mod cargo_messages;
+mod chapters;
+mod config_file;
+mod converter;
+mod diagnostic;
+mod diff;
+mod info;
+mod lock;
+mod malformed;
+mod merge;
+mod paths;
+mod pieces;
+mod roundtrip;
+mod source_map;
 
 struct DifferingLines<'a> {
     left_line_num: usize,
@@ -122,6 +136,18 @@ fn warn_test_md2rs(md: &str, rs: &str) {
     panic_if_different("actual", &output, "expect", rs);
 }
 
+#[cfg(test)]
+fn warn_test_rs2md(rs: &str, md: &str) {
+    let mut output = Vec::new();
+    match rs2md(rs.as_bytes(), &mut output) {
+        Err(super::Error::Warnings(_)) => {}
+        Ok(_) => panic!("expected successful conversion with warning"),
+        Err(_) => panic!("error in converion"),
+    }
+    let output = String::from_utf8(output).unwrap();
+    panic_if_different("actual", &output, "expect", md);
+}
+
 #[cfg(test)]
 fn core_test_rs2md(rs: &str, md: &str) {
     let mut output = Vec::new();
@@ -201,7 +227,7 @@ fn test_prodigal5_md2rs() {
 
 #[test]
 fn test_prodigal5return_md2rs() {
-   core_test_rs2md(test_snippets::HARVEST5_RS, test_snippets::RETURN5_MD);
+   core_test_rs2md(test_snippets::HARVEST5_RS, test_snippets::PRODIGAL5_MD);
 }
 
 #[test]
@@ -246,6 +272,11 @@ fn test_hello9_link_to_play_md2rs_warn() {
                     test_snippets::HELLO9_LINK_TO_PLAY_RS);
 }
 
+#[test]
+fn test_orphaned_meta_note_rs2md_warn() {
+    warn_test_rs2md("fn main() {}\n//@@,no_run\n", "```rust\nfn main() {}\n```\n");
+}
+
 #[test]
 fn test_hello10_link_to_play_eq_md2rs() {
     core_test_md2rs(test_snippets::HELLO10_LINK_TO_PLAY_EQ_MD,
@@ -281,3 +312,107 @@ fn test_hello12_link_to_play_rs2md() {
     core_test_rs2md(test_snippets::HELLO12_LINK_TO_PLAY_MARKDOWN_FOLLOW_RS,
                     test_snippets::HELLO12_LINK_TO_PLAY_MARKDOWN_FOLLOW_MD);
 }
+
+#[test]
+fn test_hello13_fence_variants_md2rs() {
+    core_test_md2rs(test_snippets::HELLO13_FENCE_VARIANTS_MD,
+                    test_snippets::HELLO13_FENCE_VARIANTS_RS);
+}
+
+#[test]
+fn test_hello13_fence_variants_rs2md() {
+    core_test_rs2md(test_snippets::HELLO13_FENCE_VARIANTS_RS,
+                    test_snippets::HELLO13_FENCE_VARIANTS_RETURN_MD);
+}
+
+#[test]
+fn test_hello14_not_rust_fences_md2rs() {
+    core_test_md2rs(test_snippets::HELLO14_NOT_RUST_FENCES_MD,
+                    test_snippets::HELLO14_NOT_RUST_FENCES_RS);
+}
+
+#[test]
+fn test_hello14_not_rust_fences_rs2md() {
+    core_test_rs2md(test_snippets::HELLO14_NOT_RUST_FENCES_RS,
+                    test_snippets::HELLO14_NOT_RUST_FENCES_MD);
+}
+
+#[test]
+fn test_hello15_info_strings_md2rs() {
+    core_test_md2rs(test_snippets::HELLO15_INFO_STRINGS_MD,
+                    test_snippets::HELLO15_INFO_STRINGS_RS);
+}
+
+#[test]
+fn test_hello15_info_strings_rs2md() {
+    core_test_rs2md(test_snippets::HELLO15_INFO_STRINGS_RS,
+                    test_snippets::HELLO15_INFO_STRINGS_MD);
+}
+
+#[test]
+fn test_hello16_foreign_blocks_md2rs() {
+    core_test_md2rs(test_snippets::HELLO16_FOREIGN_BLOCKS_MD,
+                    test_snippets::HELLO16_FOREIGN_BLOCKS_RS);
+}
+
+#[test]
+fn test_hello16_foreign_blocks_rs2md() {
+    core_test_rs2md(test_snippets::HELLO16_FOREIGN_BLOCKS_RS,
+                    test_snippets::HELLO16_FOREIGN_BLOCKS_MD);
+}
+
+#[test]
+fn test_hello17_hidden_lines_md2rs() {
+    core_test_md2rs(test_snippets::HELLO17_HIDDEN_LINES_MD,
+                    test_snippets::HELLO17_HIDDEN_LINES_RS);
+}
+
+#[test]
+fn test_hello17_hidden_lines_rs2md() {
+    core_test_rs2md(test_snippets::HELLO17_HIDDEN_LINES_RS,
+                    test_snippets::HELLO17_HIDDEN_LINES_MD);
+}
+
+#[test]
+fn test_hello18_chunks_md2rs() {
+    core_test_md2rs(test_snippets::HELLO18_CHUNKS_MD,
+                    test_snippets::HELLO18_CHUNKS_RS);
+}
+
+#[test]
+fn test_hello18_chunks_rs2md() {
+    core_test_rs2md(test_snippets::HELLO18_CHUNKS_RS,
+                    test_snippets::HELLO18_CHUNKS_MD);
+}
+
+#[cfg(test)]
+fn chunk_error(md: &str) -> super::ChunkError {
+    match md2rs(md.as_bytes(), &mut Vec::new()) {
+        Err(super::Error::ChunkError(e)) => e,
+        _ => panic!("expected a chunk error"),
+    }
+}
+
+#[test]
+fn test_undefined_chunk_is_an_error() {
+    match chunk_error("```rust <<a>>=\n```\n```rust\n<<b>>\n```\n") {
+        super::ChunkError::Undefined { ref name, line: 4, column: 1 } if name == "b" => {}
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn test_cyclic_chunks_are_an_error() {
+    match chunk_error("```rust <<a>>=\n<<b>>\n```\n```rust <<b>>=\n<<a>>\n```\n") {
+        super::ChunkError::Cyclic { ref names, line: 1, column: 1 } if *names == ["a", "b"] => {}
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn test_duplicate_chunk_is_an_error() {
+    match chunk_error("```rust <<a>>=\n```\n```rust <<a>>=\n```\n") {
+        super::ChunkError::Duplicate { ref name, line: 3, column: 1 } if name == "a" => {}
+        e => panic!("unexpected error: {}", e),
+    }
+}
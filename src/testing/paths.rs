@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+use {Config, MdPath, RsPath};
+use {default_config, DEFAULT_LIT_DIR};
+
+fn config(src: &str, lit: &str) -> Config {
+    let mut c = Config::new();
+    c.set_src_dir(src.to_string()).set_lit_dir(lit.to_string());
+    c
+}
+
+#[test]
+fn default_dirs_map_in_place() {
+    let c = Config::new();
+    let rs = RsPath::new(PathBuf::from("src/a/b.rs"), &c).unwrap();
+    assert_eq!(rs.to_md(&c).unwrap().0, PathBuf::from("src/a/b.md"));
+}
+
+#[test]
+fn multi_component_roots_map_both_ways() {
+    let c = config("crates/foo/src", "docs/lit/foo");
+    let rs = RsPath::new(PathBuf::from("crates/foo/src/lib.rs"), &c).unwrap();
+    assert_eq!(rs.to_md(&c).unwrap().0, PathBuf::from("docs/lit/foo/lib.md"));
+    let md = MdPath::new(PathBuf::from("docs/lit/foo/net/tcp.md"), &c).unwrap();
+    assert_eq!(md.to_rs(&c).unwrap().0, PathBuf::from("crates/foo/src/net/tcp.rs"));
+}
+
+#[test]
+fn roots_of_different_depths_round_trip() {
+    let c = config("src", "docs/lit");
+    let rs = RsPath::new(PathBuf::from("src/x/y/z.rs"), &c).unwrap();
+    let md = rs.to_md(&c).unwrap();
+    assert_eq!(md.0, PathBuf::from("docs/lit/x/y/z.md"));
+    assert_eq!(md.to_rs(&c).unwrap().0, rs.0);
+}
+
+#[test]
+fn roots_are_resolved_against_the_project_root() {
+    let mut c = config("crates/foo/src", "lit");
+    c.set_root("/work/proj");
+    let rs = RsPath::new(PathBuf::from("/work/proj/crates/foo/src/m/mod.rs"), &c).unwrap();
+    assert_eq!(rs.to_md(&c).unwrap().0, PathBuf::from("/work/proj/lit/m/mod.md"));
+}
+
+#[test]
+fn files_outside_their_directory_have_no_twin() {
+    let c = config("src", "lit");
+    match MdPath::new(PathBuf::from("src/NOTES.md"), &c) {
+        Err(::Error::ConfigError(_)) => {}
+        _ => panic!("expected a configuration error"),
+    }
+    assert!(RsPath::new(PathBuf::from("lit/a.rs"), &c).is_err());
+}
+
+#[test]
+fn mapped_files_pair_with_each_other() {
+    let mut c = Config::new();
+    c.map_file("src/lib.rs", "README.md");
+    let rs = RsPath::new(PathBuf::from("src/lib.rs"), &c).unwrap();
+    assert_eq!(rs.to_md(&c).unwrap().0, PathBuf::from("README.md"));
+    let md = MdPath::new(PathBuf::from("README.md"), &c).unwrap();
+    assert_eq!(md.to_rs(&c).unwrap().0, PathBuf::from("src/lib.rs"));
+    // Other files are unaffected.
+    let rs = RsPath::new(PathBuf::from("src/main.rs"), &c).unwrap();
+    assert_eq!(rs.to_md(&c).unwrap().0, PathBuf::from("src/main.md"));
+}
+
+#[test]
+fn include_and_exclude_select_pairs() {
+    let mut c = Config::new();
+    c.include("src/**/*.rs").unwrap().exclude("src/generated/**").unwrap();
+    let selects = |rs: &str, md: &str| c.selects(Path::new(rs), Path::new(md));
+    assert!(selects("src/a.rs", "src/a.md"));
+    assert!(selects("src/m/b.rs", "src/m/b.md"));
+    assert!(!selects("src/generated/c.rs", "src/generated/c.md"));
+
+    let mut c = Config::new();
+    c.include("src/*.md").unwrap();
+    // `*` does not cross directory separators.
+    assert!(c.selects(Path::new("src/a.rs"), Path::new("src/a.md")));
+    assert!(!c.selects(Path::new("src/m/b.rs"), Path::new("src/m/b.md")));
+}
+
+#[test]
+fn mapped_files_ignore_exclude_patterns() {
+    let mut c = Config::new();
+    c.exclude("**").unwrap();
+    c.map_file("src/lib.rs", "README.md");
+    assert!(c.selects(Path::new("src/lib.rs"), Path::new("README.md")));
+    assert!(!c.selects(Path::new("src/a.rs"), Path::new("src/a.md")));
+}
+
+#[test]
+fn invalid_pattern_is_an_error() {
+    match Config::new().include("src/[") {
+        Err(::Error::ConfigError(_)) => {}
+        _ => panic!("expected a configuration error"),
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_dir_functions_set_the_default_config() {
+    use {get_lit_dir, get_src_dir, set_src_dir};
+    assert_eq!(get_lit_dir(), DEFAULT_LIT_DIR);
+    set_src_dir("crates/foo/src".to_string());
+    assert_eq!(get_src_dir(), "crates/foo/src");
+    assert_eq!(default_config().src_dir(), "crates/foo/src");
+    assert_eq!(default_config().lit_dir(), DEFAULT_LIT_DIR);
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use {Config, Converter};
+use {md2rs, rs2md};
+
+const DESIGN_MD: &str = "\
+# Design
+
+```rust
+mod lexer;
+```
+
+The lexer:
+
+```rust,file=src/lexer.rs
+pub fn lex() {}
+```
+
+```rust,ignore,file=src/lexer.rs
+lex();
+```
+";
+
+const DESIGN_RS: &str = "\
+//@ # Design
+
+mod lexer;
+
+//@ The lexer:
+
+//@@,file=src/lexer.rs
+
+//@@,ignore,file=src/lexer.rs
+";
+
+const LEXER_RS: &str = "\
+//@from lit/design.md
+pub fn lex() {}
+//@from lit/design.md
+// lex();
+";
+
+fn split(md: &str) -> (String, BTreeMap<String, Vec<u8>>) {
+    let mut converter = md2rs::Converter::new(&Config::new());
+    converter.split_files("lit/design.md");
+    let mut out = Vec::new();
+    let pieces = converter.convert_all(md.as_bytes(), &mut out).ok().unwrap().pieces;
+    (String::from_utf8(out).unwrap(), pieces)
+}
+
+#[test]
+fn file_blocks_are_written_to_their_files() {
+    let (rs, pieces) = split(DESIGN_MD);
+    assert_eq!(rs, DESIGN_RS);
+    assert_eq!(pieces.keys().collect::<Vec<_>>(), vec!["src/lexer.rs"]);
+    assert_eq!(String::from_utf8_lossy(&pieces["src/lexer.rs"]), LEXER_RS);
+}
+
+#[test]
+fn rs2md_fills_file_blocks_from_their_files() {
+    let config = Config::new();
+    let mut converter = rs2md::Converter::new(&config);
+    converter.add_pieces(config.resolve("src/lexer.rs"), LEXER_RS);
+    let mut out = Vec::new();
+    converter.convert_str(DESIGN_RS, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), DESIGN_MD);
+}
+
+#[test]
+fn file_blocks_stay_in_place_unless_splitting() {
+    let mut out = Vec::new();
+    super::md2rs(DESIGN_MD.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("//@@,file=src/lexer.rs\npub fn lex() {}\n"), "{}", out);
+}
+
+#[test]
+fn file_blocks_may_write_only_rs_files_under_src() {
+    let config = Config::new();
+    assert_eq!(config.piece_path("src/lexer.rs"), Some(config.resolve("src/lexer.rs")));
+    for file in &["../victim.rs", "src/../../victim.rs", "/etc/victim.rs", "src/notes.txt", "tests/lexer.rs", "./src/a.rs"] {
+        assert_eq!(config.piece_path(file), None, "{}", file);
+    }
+}
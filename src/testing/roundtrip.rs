@@ -0,0 +1,166 @@
+use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+use {md_to_rs, normalize_md, normalize_rs, rs_to_md, Warning};
+
+// The lines that generated `.md` files are made of: text that looks
+// like tango's markers, fences of every kind (some left open), code
+// with hidden lines, chunks, and blank lines, some of them not quite.
+pub const MD_LINES: &[&str] = &[
+    "# Title", "Some text.", "- an item", "> quoted", "    indented", "hard break  ", "\tx",
+    "//@ not a marker", "//@", "<<not a chunk>>", "[x]: https://play.rust-lang.org/?code=x",
+    "", "", "", "  ",
+    "```rust", "```rust", "```rust,ignore", "```rust,no_run", "```{.rust .numberLines}",
+    "```rust <<setup>>=", "  ```rust", "~~~rust", "```toml", "```", "```", "```", "~~~", "````",
+    "~~~rust", "~~~rust,ignore `x`", "~~~{.rust title=\"`a`\"}", "~~~~", "~~~", "~~~",
+    "```rust,title=\"a\\\"b\"", "```{.rust\\-}", "~~~rust>\\`", "```rust\\<",
+    "fn main() {}", "    let x = 1;", "# use std::io;", "#", "##[x]", "// comment", "    <<setup>>",
+    "let s = \"```\";",
+];
+
+// The lines that generated `.rs` files are made of, as for `MD_LINES`.
+pub const RS_LINES: &[&str] = &[
+    "//@ # Title", "//@ Some text.", "//@ hard break  ", "//@", "//@ ", "//@x", "//@  two",
+    "//@@,ignore", "//@@ {.numberLines}", "//@@", "//@@@ name", "//@#", "//@ ```toml", "//@ ```",
+    "//@@ `x`", "//@@ { title=\"`a`\"}", "//@ ~~~", "~~~", "    ~~~~", "```",
+    "//@@ {\\<", "//@@>\\`", "//@@~\\>", "//@@ {\\-", "//@@,title=\"a\\\"b\"", "//@@\\\\",
+    "fn main() {}", "    let x = 1;", "", "", "  ", "// comment", "    //@ indented note",
+    "    //@<< setup", "    //@>>", "//@@ <<setup>>=", "#", "# x", "##y", "\tz",
+];
+
+// A text, as the lines it picks (by index, wrapping around) from one
+// of the lists above; shrinking drops and simplifies picks.
+#[derive(Clone, Debug)]
+pub struct Picks(Vec<u8>);
+
+impl Arbitrary for Picks {
+    fn arbitrary(g: &mut Gen) -> Picks {
+        Picks(Vec::arbitrary(g))
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Picks>> {
+        Box::new(self.0.shrink().map(Picks))
+    }
+}
+
+impl Picks {
+    pub fn text(&self, lines: &[&str]) -> String {
+        self.0.iter().map(|&i| format!("{}\n", lines[i as usize % lines.len()])).collect()
+    }
+}
+
+pub fn check(tests: u64, prop: fn(Picks) -> TestResult) {
+    QuickCheck::new().tests(tests).max_tests(tests * 10).quickcheck(prop);
+}
+
+#[test]
+fn normal_md_converts_back_unchanged() {
+    fn prop(picks: Picks) -> TestResult {
+        let md = picks.text(MD_LINES);
+        let rs = match md_to_rs(&md) {
+            Ok(ref rs) if rs.warnings.is_empty() => rs.output.clone(),
+            _ => return TestResult::discard(),
+        };
+        let normal = normalize_md(&md).unwrap().output;
+        let normal_rs = md_to_rs(&normal).unwrap().output;
+        TestResult::from_bool(normal_rs == rs && rs_to_md(&normal_rs).unwrap().output == normal)
+    }
+    check(2000, prop);
+}
+
+#[test]
+fn normal_rs_converts_back_unchanged() {
+    fn prop(picks: Picks) -> TestResult {
+        let rs = picks.text(RS_LINES);
+        let normal = match normalize_rs(&rs) {
+            Ok(ref normal) if normal.warnings.is_empty() => normal.output.clone(),
+            _ => return TestResult::discard(),
+        };
+        let md = rs_to_md(&normal).unwrap().output;
+        TestResult::from_bool(md_to_rs(&md).unwrap().output == normal)
+    }
+    check(2000, prop);
+}
+
+// The characters info strings are made of, heavy on those that mean
+// something in one: escapes, entities, braces, quotes and fences.
+const INFO_CHARS: &[char] = &['\\', '`', '~', '{', '}', '<', '>', '-', '.', ',', '=', '"', '&', ';', '#', ' ', 'a', 'x'];
+
+// Normalization can hide a converter that changes an info string, as
+// long as the changed string is stable: this one takes a single Rust
+// block through both converters and wants its info string back.
+#[test]
+fn info_strings_survive_conversion() {
+    fn prop(picks: Picks) -> TestResult {
+        let rest: String = picks.0.iter().map(|&i| INFO_CHARS[i as usize % INFO_CHARS.len()]).collect();
+        let fence = if rest.contains('`') { "~~~" } else { "```" };
+        let md = format!("{}rust{}\nfn f() {{}}\n{}\n", fence, rest.trim_end(), fence);
+        let rs = match md_to_rs(&md) {
+            Ok(ref rs) if rs.warnings.is_empty() && !rs.output.starts_with("//@ ") => rs.output.clone(),
+            _ => return TestResult::discard(),
+        };
+        TestResult::from_bool(rs_to_md(&rs).unwrap().output == md)
+    }
+    check(2000, prop);
+}
+
+// Checks that `md` is in normal form, converting to `rs` and back.
+fn round_trips(md: &str, rs: &str) {
+    let converted = md_to_rs(md).unwrap();
+    assert_eq!(converted.warnings, vec![]);
+    assert_eq!(converted.output, rs);
+    assert_eq!(rs_to_md(rs).unwrap().output, md);
+}
+
+#[test]
+fn blank_lines_at_the_ends_of_blocks_go_outside() {
+    let md = "Text\n```rust\n\nfn f() {}\n\n```\nMore\n";
+    assert_eq!(normalize_md(md).unwrap().output, "Text\n\n```rust\nfn f() {}\n```\n\nMore\n");
+    round_trips("Text\n\n```rust\nfn f() {}\n```\n\nMore\n", "//@ Text\n\nfn f() {}\n\n//@ More\n");
+}
+
+#[test]
+fn adjacent_blocks_stay_apart() {
+    round_trips("```rust\nfn a() {}\n```\n```rust,ignore\n\nfn b() {}\n```\n\n```rust\nfn c() {}\n```\n",
+                "fn a() {}\n//@@,ignore\n\n// fn b() {}\n\n//@@\nfn c() {}\n");
+}
+
+#[test]
+fn whitespace_is_kept_in_text() {
+    round_trips("Line one  \nline two\n\t\n```rust\nfn f() {}\n```\n",
+                "//@ Line one  \n//@ line two\n//@ \t\nfn f() {}\n");
+    assert_eq!(normalize_md("```rust\nfn f() {\n  \n}\n```\n").unwrap().output, "```rust\nfn f() {\n\n}\n```\n");
+}
+
+#[test]
+fn rustdoc_info_strings_stay_rustdoc() {
+    round_trips("```rust {.numberLines}\nfn f() {}\n```\n", "//@@\\ {.numberLines}\nfn f() {}\n");
+    round_trips("```{.rust .numberLines}\nfn f() {}\n```\n", "//@@ { .numberLines}\nfn f() {}\n");
+}
+
+#[test]
+fn escapes_in_info_strings_are_kept() {
+    round_trips("```rust,title=\"a\\\"b\"\nfn f() {}\n```\n", "//@@,title=\"a\\\"b\"\nfn f() {}\n");
+    for note in &["//@@>\\`", "//@@~\\>", "//@@ {\\<", "//@@ {\\-", "//@@,a&amp;b"] {
+        let rs = format!("{}\nfn f() {{}}\n", note);
+        let converted = md_to_rs(&rs_to_md(&rs).unwrap().output).unwrap();
+        assert_eq!(converted.warnings, vec![]);
+        assert_eq!(converted.output, rs);
+    }
+}
+
+#[test]
+fn code_that_looks_hidden_is_escaped() {
+    round_trips("```rust\n##\n## shown\n###[x]\n# hidden\n```\n", "#\n# shown\n##[x]\n//@#\nhidden\n");
+}
+
+#[test]
+fn empty_blocks_are_carried_as_text() {
+    round_trips("```rust,ignore\n```\n\n```rust\n\n```\n", "//@ ```rust,ignore\n//@ ```\n//@\n//@ ```rust\n//@\n//@ ```\n");
+}
+
+#[test]
+fn code_starting_like_a_marker_is_warned_of() {
+    let converted = md_to_rs("```rust\nfn f() {}\n//@ x\n```\n").unwrap();
+    assert_eq!(converted.warnings, vec![Warning::MarkerInCode { code: "//@ x".to_string(), line: 3, column: 1 }]);
+    // Commented out, it is safe.
+    assert_eq!(md_to_rs("```rust,ignore\n//@ x\n```\n").unwrap().warnings, vec![]);
+}
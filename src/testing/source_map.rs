@@ -0,0 +1,86 @@
+use lock::ContentHash;
+use md2rs;
+use source_map::{Origin, SourceMap};
+use std::path::{Path, PathBuf};
+use Config;
+
+const DOC_MD: &str = "\
+# Title
+
+```rust
+fn main() {
+# use std::io;
+}
+```
+";
+
+const CHUNKS_MD: &str = "\
+```rust
+fn main() {
+    <<setup>>
+}
+```
+
+```rust <<setup>>=
+let x = 1;
+```
+";
+
+// Converts `md`, returning the `.rs` text and where its lines came from.
+fn convert(md: &str) -> (String, Vec<Origin>) {
+    let mut out = Vec::new();
+    let mut outputs = md2rs::Converter::new(&Config::new()).convert_all(md.as_bytes(), &mut out).ok().unwrap();
+    (String::from_utf8(out).unwrap(), outputs.origins.remove(&None).unwrap())
+}
+
+fn map_of(md: &str) -> SourceMap {
+    SourceMap::new(ContentHash(7), vec![PathBuf::from("src/lib.md")], convert(md).1)
+}
+
+#[test]
+fn every_line_written_has_an_origin() {
+    let (rs, origins) = convert(DOC_MD);
+    assert_eq!(rs.lines().count(), origins.len());
+    let lines: Vec<usize> = origins.iter().map(|o| o.line).collect();
+    assert_eq!(lines, vec![1, 2, 4, 5, 5, 6]);
+}
+
+#[test]
+fn columns_account_for_prefixes() {
+    let map = map_of(DOC_MD);
+    let md = Path::new("src/lib.md");
+    // `//@ # Title`: column 5 is the `#`.
+    assert_eq!(map.to_md(1, 5), Some((md, 1, 1)));
+    // `use std::io;`, which is `# use std::io;` in the `.md` file.
+    assert_eq!(map.to_md(5, 1), Some((md, 5, 3)));
+    assert_eq!(map.to_rs(md, 5, 3), Some((4, 1)));
+    assert_eq!(map.to_rs(md, 4, 4), Some((3, 4)));
+    assert_eq!(map.to_rs(Path::new("src/other.md"), 4, 4), None);
+    assert_eq!(map.to_md(99, 1), None);
+}
+
+#[test]
+fn expansions_map_to_chunk_definitions() {
+    let (rs, origins) = convert(CHUNKS_MD);
+    let i = rs.lines().position(|l| l == "    let x = 1;").unwrap();
+    assert_eq!((origins[i].line, origins[i].shift), (8, -4));
+}
+
+#[test]
+fn map_round_trips_through_text() {
+    let map = SourceMap::new(ContentHash(0x1f), vec![PathBuf::from("lit/a.md"), PathBuf::from("lit/b.md")],
+                             vec![Origin { source: 0, line: 1, shift: -4 },
+                                  Origin { source: 1, line: 3, shift: 2 }]);
+    let mut text = Vec::new();
+    map.write_to(&mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains("\nhash\t000000000000001f\nsource\tlit/a.md\n"), "{}", text);
+    assert_eq!(SourceMap::parse(&text).unwrap(), map);
+}
+
+#[test]
+fn malformed_maps_are_rejected() {
+    assert!(SourceMap::parse("source\tlit/a.md\n1\t0\t1\t0\n").is_err());
+    assert!(SourceMap::parse("hash\t1f\nsource\tlit/a.md\n1\t1\t1\t0\n").is_err());
+    assert!(SourceMap::parse("hash\t1f\nsource\tlit/a.md\n2\t0\t1\t0\n").is_err());
+}
@@ -106,15 +106,6 @@ let code_fragment;
 //@ but note the tab
 "#;
 
-pub const RETURN5_MD: &'static str = r#"# Hello World
-```rust
-let code_fragment;
-```
-
-This looks like it has a nice para break before its starts,
-but note the tab
-"#;
-
 pub const HELLO6_METADATA_MD: &'static str = r#"# Hello World
 
 ```{.rust .css_class_metadata }
@@ -221,3 +212,251 @@ Hi
 
 //@ Content
 "#;
+
+pub const HELLO13_FENCE_VARIANTS_MD: &'static str = r#"# Fences
+  ```rust
+  fn indented() {}
+  ```
+
+~~~rust
+fn tildes() {}
+~~~
+
+````rust
+fn backticks() { let _ = r"
+```
+"; }
+````
+"#;
+
+pub const HELLO13_FENCE_VARIANTS_RS: &'static str = r#"//@ # Fences
+fn indented() {}
+
+//@@
+fn tildes() {}
+
+//@@
+fn backticks() { let _ = r"
+```
+"; }
+"#;
+
+// The blocks come back with plain fences, but for the last, fenced so
+// that the backticks inside do not close it.
+pub const HELLO13_FENCE_VARIANTS_RETURN_MD: &'static str = r#"# Fences
+```rust
+fn indented() {}
+```
+
+```rust
+fn tildes() {}
+```
+
+````rust
+fn backticks() { let _ = r"
+```
+"; }
+````
+"#;
+
+pub const HELLO14_NOT_RUST_FENCES_MD: &'static str = r#"# Not Rust
+````markdown
+```rust
+fn quoted() {}
+```
+````
+
+```rust fn inline() {}``` is inline code.
+
+> ```rust
+> fn in_quote() {}
+> ```
+"#;
+
+pub const HELLO14_NOT_RUST_FENCES_RS: &'static str = r#"//@ # Not Rust
+//@ ````markdown
+//@ ```rust
+//@ fn quoted() {}
+//@ ```
+//@ ````
+//@
+//@ ```rust fn inline() {}``` is inline code.
+//@
+//@ > ```rust
+//@ > fn in_quote() {}
+//@ > ```
+"#;
+
+pub const HELLO15_INFO_STRINGS_MD: &'static str = r#"# Info strings
+```rust,ignore
+fn main() { this does not compile }
+// nor is it meant to
+```
+Text.
+```rust,no_run,edition2021
+fn main() { loop {} }
+```
+Text.
+```rust ignore
+fn main() { neither does this }
+```
+Text.
+```{.rust .numberLines startFrom="3"}
+fn main() {}
+```
+"#;
+
+pub const HELLO15_INFO_STRINGS_RS: &'static str = r#"//@ # Info strings
+//@@,ignore
+// fn main() { this does not compile }
+// // nor is it meant to
+//@ Text.
+//@@,no_run,edition2021
+fn main() { loop {} }
+//@ Text.
+//@@ ignore
+// fn main() { neither does this }
+//@ Text.
+//@@ { .numberLines startFrom="3"}
+fn main() {}
+"#;
+
+pub const HELLO16_FOREIGN_BLOCKS_MD: &'static str = "# Foreign blocks
+```toml
+[dependencies]
+
+tango = \"0.8\"
+```
+```sh
+//@ not a tango marker
+//@@@ nor this
+  
+[hello16]: https://play.rust-lang.org/?code=Hi&version=nightly
+```
+````markdown
+```rust
+fn quoted() {}
+```
+
+~~~
+```
+~~~
+````
+```
+plain
+```
+";
+
+pub const HELLO16_FOREIGN_BLOCKS_RS: &'static str = "//@ # Foreign blocks
+//@ ```toml
+//@ [dependencies]
+//@
+//@ tango = \"0.8\"
+//@ ```
+//@ ```sh
+//@ //@ not a tango marker
+//@ //@@@ nor this
+//@   
+//@ [hello16]: https://play.rust-lang.org/?code=Hi&version=nightly
+//@ ```
+//@ ````markdown
+//@ ```rust
+//@ fn quoted() {}
+//@ ```
+//@
+//@ ~~~
+//@ ```
+//@ ~~~
+//@ ````
+//@ ```
+//@ plain
+//@ ```
+";
+
+pub const HELLO17_HIDDEN_LINES_MD: &'static str = r#"# Hidden lines
+```rust
+# use std::fmt;
+#
+#[derive(Debug)]
+struct S;
+
+# fn main() {
+println!("{:?}", S);
+# }
+```
+Ignored, too:
+```rust,ignore
+# let hidden = ();
+```
+"#;
+
+pub const HELLO17_HIDDEN_LINES_RS: &'static str = r#"//@ # Hidden lines
+//@#
+use std::fmt;
+//@#
+
+#[derive(Debug)]
+struct S;
+
+//@#
+fn main() {
+println!("{:?}", S);
+//@#
+}
+//@ Ignored, too:
+//@@,ignore
+//@#
+// let hidden = ();
+"#;
+
+pub const HELLO18_CHUNKS_MD: &'static str = r#"# Chunks
+The program reads a header and greets it.
+```rust
+fn main() {
+    let input = "tango";
+    <<parse header>>
+    println!("Hello {}", header);
+}
+```
+Parsing the header is just a matter of trimming.
+```rust <<parse header>>=
+# // (hidden)
+let header = input.trim();
+<<check header>>
+```
+A chunk may refer to other chunks.
+```rust <<check header>>=
+if header.is_empty() {
+    return;
+}
+```
+A chunk that is never referred to stays where it is.
+```rust <<unused>>=
+fn unused() {}
+```
+"#;
+
+pub const HELLO18_CHUNKS_RS: &'static str = r#"//@ # Chunks
+//@ The program reads a header and greets it.
+fn main() {
+    let input = "tango";
+    //@<< parse header
+    //@#
+    // (hidden)
+    let header = input.trim();
+    //@<< check header
+    if header.is_empty() {
+        return;
+    }
+    //@>>
+    //@>>
+    println!("Hello {}", header);
+}
+//@ Parsing the header is just a matter of trimming.
+//@@ <<parse header>>=
+//@ A chunk may refer to other chunks.
+//@@ <<check header>>=
+//@ A chunk that is never referred to stays where it is.
+//@@ <<unused>>=
+fn unused() {}
+"#;
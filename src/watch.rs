@@ -0,0 +1,204 @@
+//! Re-syncing `.rs`/`.md` pairs as their files change.
+//!
+//! The watcher polls the source and literate directories for changes
+//! to file modification times (there is no portable file-notification
+//! API in `std`). Once a burst of changes has settled for the
+//! debounce interval, it runs the usual sync pipeline restricted to
+//! the pairs that changed. If a source is modified again while its
+//! pair is being synced, the pipeline's concurrent-update check fails
+//! and the pair is retried after the next quiet interval, rather than
+//! being clobbered with stale content.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use walkdir::WalkDir;
+
+use super::{Config, Context, Error, Result, RsPath, MdPath, Extensions, STAMP};
+use super::{candidates, keep_file_name};
+
+/// How long the watched files must stay unchanged before a sync runs,
+/// by default.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// How often the watched directories are scanned, by default.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Watcher {
+    config: Config,
+    debounce: Duration,
+    // Modification time of every `.rs` and `.md` file as of the last
+    // scan.
+    snapshot: BTreeMap<PathBuf, SystemTime>,
+    // `.rs` paths of the pairs changed since they were last synced.
+    pending: BTreeSet<PathBuf>,
+    last_change: Option<Instant>,
+}
+
+impl Watcher {
+    pub fn new(config: Config) -> Result<Watcher> {
+        let config = (config.load_files())?;
+        let mut w = Watcher {
+            config,
+            debounce: DEFAULT_DEBOUNCE,
+            snapshot: BTreeMap::new(),
+            pending: BTreeSet::new(),
+            last_change: None,
+        };
+        w.snapshot = (w.scan())?;
+        Ok(w)
+    }
+
+    pub fn set_debounce(&mut self, debounce: Duration) -> &mut Watcher {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Scans for changes and, if changes have settled, syncs the
+    /// affected pairs. Returns the `.rs` paths of the pairs synced.
+    pub fn poll(&mut self) -> Result<Vec<PathBuf>> {
+        let snapshot = (self.scan())?;
+        let mut changed = Vec::new();
+        for (p, t) in &snapshot {
+            if self.snapshot.get(p) != Some(t) {
+                changed.push(p.clone());
+            }
+        }
+        for p in self.snapshot.keys() {
+            if !snapshot.contains_key(p) {
+                changed.push(p.clone());
+            }
+        }
+        self.snapshot = snapshot;
+        if !changed.is_empty() {
+            let pairs = (self.pairs())?;
+            for p in changed {
+                if let Some(rs) = pairs.get(&p) {
+                    self.pending.insert(rs.clone());
+                }
+            }
+            self.last_change = Some(Instant::now());
+        }
+
+        match self.last_change {
+            Some(t) if !self.pending.is_empty() && t.elapsed() >= self.debounce => {}
+            _ => return Ok(Vec::new()),
+        }
+
+        let only: Vec<PathBuf> = self.pending.iter().cloned().collect();
+        let result = self.sync(only.clone());
+        // Pick up the files we just wrote, so that they are not
+        // mistaken for edits on the next poll.
+        self.snapshot = (self.scan())?;
+        match result {
+            Err(Error::ConcurrentUpdate { ref path_buf, .. }) => {
+                println!("{} changed while syncing; will retry", path_buf.display());
+                self.last_change = Some(Instant::now());
+                Ok(Vec::new())
+            }
+            Err(e) => {
+                self.pending.clear();
+                Err(e)
+            }
+            Ok(()) => {
+                self.pending.clear();
+                Ok(only)
+            }
+        }
+    }
+
+    /// Polls forever, reporting (but otherwise surviving) errors from
+    /// individual syncs.
+    pub fn run(&mut self, interval: Duration) -> Result<()> {
+        loop {
+            match self.poll() {
+                Ok(synced) => {
+                    for rs in synced {
+                        println!("synced {}", rs.display());
+                    }
+                }
+                Err(e @ Error::IoError(_)) => return Err(e),
+                Err(e) => println!("sync failed: {}", e),
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    // Maps each file of the pairs that a sync takes up to the `.rs`
+    // path of its pair. A file written from `file=` blocks is synced
+    // with the pair whose `.md` file it comes from; files that no sync
+    // would touch are left out.
+    fn pairs(&self) -> Result<BTreeMap<PathBuf, PathBuf>> {
+        let (rs_paths, md_paths) = (candidates(&self.config, false))?;
+        let mut pairs = BTreeMap::new();
+        let mut add = |rs: &RsPath, md: &MdPath| {
+            let files = Some(&rs.0).into_iter().chain(&rs.1).chain(Some(&md.0)).chain(&md.1);
+            for p in files {
+                pairs.insert(p.clone(), rs.0.clone());
+            }
+        };
+        for rs in &rs_paths {
+            add(rs, &(rs.to_md(&self.config))?);
+        }
+        for md in &md_paths {
+            add(&(md.to_rs(&self.config))?, md);
+        }
+        Ok(pairs)
+    }
+
+    fn scan(&self) -> Result<BTreeMap<PathBuf, SystemTime>> {
+        let mut snapshot = BTreeMap::new();
+        // As for a sync, only `.rs` files among the sources and `.md`
+        // files among the literate files are looked at.
+        for &(dir, is_rs) in &[(self.config.src_dir(), true), (self.config.lit_dir(), false)] {
+            let dir = self.config.resolve(dir);
+            if !dir.exists() {
+                continue;
+            }
+            for ent in WalkDir::new(dir).into_iter() {
+                let ent = (ent)?;
+                let p = ent.path();
+                if keep_file_name(p).is_err() || !(if is_rs { p.rs_extension() } else { p.md_extension() }) {
+                    continue;
+                }
+                // The file may vanish between the walk and the stat.
+                if let Ok(t) = fs::metadata(p).and_then(|m| m.modified()) {
+                    snapshot.insert(p.to_path_buf(), t);
+                }
+            }
+        }
+        let mapped = self.config.mappings.iter().map(|(rs, md)| (rs, vec![md]));
+        let chapters = self.config.chapters.iter().map(|(rs, mds)| (rs, mds.iter().collect()));
+        for (rs, mds) in mapped.chain(chapters) {
+            for p in Some(rs).into_iter().chain(mds) {
+                let p = self.config.resolve(p);
+                if let Ok(t) = fs::metadata(&p).and_then(|m| m.modified()) {
+                    snapshot.insert(p, t);
+                }
+            }
+        }
+        Ok(snapshot)
+    }
+
+    fn sync(&self, only: Vec<PathBuf>) -> Result<()> {
+        let stamp_path = self.config.resolve(STAMP);
+        let stamp = if stamp_path.exists() { Some((File::open(stamp_path))?) } else { None };
+        let had_stamp = stamp.is_some();
+        let mut c = (Context::new(stamp, self.config.clone()))?;
+        c.emit_rerun_if = false;
+        c.only = Some(only);
+        (c.gather_inputs())?;
+        (c.generate_content())?;
+        (c.check_input_timestamps())?;
+        if !had_stamp {
+            (c.create_stamp())?;
+        }
+        (c.adjust_stamp_timestamp())?;
+        (c.update_lock())?;
+        (c.record_maps())?;
+        Ok(())
+    }
+}
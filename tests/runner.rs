@@ -19,7 +19,9 @@ use std::fmt;
 use std::fs::{self, File};
 use std::io::{self, Read, Write};
 use std::path::{PathBuf};
-use std::process::{Command};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 const BINARY_FILENAME: &'static str = "tango";
 const PRESERVE_TEMP_DIRS: bool = false;
@@ -133,8 +135,8 @@ fn setup_src_and_lit_dirs() {
     CURRENT_DIR_PREFIX.with(|p| {
         let p = p.borrow_mut();
         let mut p_src = p.clone();
-	let src_dir = &tango::get_src_dir();
-	let lit_dir = &tango::get_lit_dir();
+	let src_dir = tango::DEFAULT_SRC_DIR;
+	let lit_dir = tango::DEFAULT_LIT_DIR;
         p_src.push(src_dir);
         fs::create_dir(p_src).unwrap_or_panic(&format!("failed to create {}", src_dir));
         if lit_dir == src_dir { return; }
@@ -151,8 +153,8 @@ impl Target {
         CURRENT_DIR_PREFIX.with(|p| {
             let p = p.borrow_mut();
             let mut p = p.clone();
-            let src_dir = tango::get_src_dir();
-            let lit_dir = tango::get_lit_dir();
+            let src_dir = tango::DEFAULT_SRC_DIR;
+            let lit_dir = tango::DEFAULT_LIT_DIR;
             match *self {
                 Target::Root => {}
                 Target::Src => p.push(src_dir),
@@ -282,41 +284,73 @@ impl convert::From<io::Error> for TangoRunError {
 }
 
 fn run_tango() -> Result<(), TangoRunError> {
-    CURRENT_DIR_PREFIX.with(|p| -> Result<(), TangoRunError> {
+    run_tango_with(&[])
+}
+
+fn tango_output(args: &[&str]) -> io::Result<Output> {
+    CURRENT_DIR_PREFIX.with(|p| {
         let p = p.borrow_mut();
         let result = infer_target_binary();
         // println!("result {:?}", result);
-        let output = match Command::new(result)
+        Command::new(result)
+            .args(args)
             .current_dir(&*p)
-            .output() {
-                Ok(o) => o,
-                Err(e) => return Err(TangoRunError::IoError(e)),
-            };
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        if REJECT_IF_TANGO_AFFECTS_STD_PORTS &&
-            stdout.len() > 0 || stderr.len() > 0
-        {
-            return Err(TangoRunError::SawOutput {
-                stdout_len: stdout.len(),
-                stderr_len: stderr.len(),
-                stdout: format!("output on stdout: `{}`", stdout),
-                stderr: format!("output on stderr: `{}`", stderr),
-                combined: format!("output on stderr: `{err}`, stdout: `{out}`",
-                                  err=stderr, out=stdout),
-            });
-        } else {
-            for line in stdout.lines() {
-                println!("stdout: {}", line);
-            }
-            for line in stderr.lines() {
-                println!("stderr: {}", line);
-            }
-        }
-        Ok(())
+            .output()
     })
 }
 
+fn tango_output_with_input(args: &[&str], input: &str) -> io::Result<Output> {
+    let mut child = CURRENT_DIR_PREFIX.with(|p| {
+        let p = p.borrow_mut();
+        Command::new(infer_target_binary())
+            .args(args)
+            .current_dir(&*p)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+    })?;
+    (child.stdin.take().unwrap().write_all(input.as_bytes()))?;
+    child.wait_with_output()
+}
+
+fn spawn_tango(args: &[&str]) -> io::Result<Child> {
+    CURRENT_DIR_PREFIX.with(|p| {
+        let p = p.borrow_mut();
+        Command::new(infer_target_binary())
+            .args(args)
+            .current_dir(&*p)
+            .stdout(Stdio::null())
+            .spawn()
+    })
+}
+
+fn run_tango_with(args: &[&str]) -> Result<(), TangoRunError> {
+    let output = (tango_output(args))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if REJECT_IF_TANGO_AFFECTS_STD_PORTS &&
+        !stdout.is_empty() || !stderr.is_empty()
+    {
+        return Err(TangoRunError::SawOutput {
+            stdout_len: stdout.len(),
+            stderr_len: stderr.len(),
+            stdout: format!("output on stdout: `{}`", stdout),
+            stderr: format!("output on stderr: `{}`", stderr),
+            combined: format!("output on stderr: `{err}`, stdout: `{out}`",
+                              err=stderr, out=stdout),
+        });
+    } else {
+        for line in stdout.lines() {
+            println!("stdout: {}", line);
+        }
+        for line in stderr.lines() {
+            println!("stderr: {}", line);
+        }
+    }
+    Ok(())
+}
+
 fn report_dir_contents(prefix: &str) {
     if !REPORT_DIR_CONTENTS { return; }
     CURRENT_DIR_PREFIX.with(|p| {
@@ -635,3 +669,798 @@ fn stamped_then_update_src() {
         }
     }).unwrap_or_panic("test error")
 }
+
+#[test]
+fn locked_then_timestamps_scrambled() {
+    framework(Test {
+        name: "locked_then_timestamps_scrambled",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", HELLO_WORLD_MD, TIME_B1))?;
+            (run_tango())?;
+            // Simulate a checkout that rewrites every timestamp,
+            // leaving `tango.stamp` older than both files.
+            (touch_file(Target::Src, "foo.rs", TIME_C1))?;
+            touch_file(Target::Lit, "foo.md", TIME_C2)
+        },
+        pre: || {
+            assert!(Target::Root.path_buf(tango::LOCK).exists());
+            let stamp_t = (Target::Root.path_buf(tango::STAMP).metadata())?.timestamp();
+            assert!(TIME_B1 == stamp_t, "stamp_t: {:?} TIME_B1: {:?}", stamp_t, TIME_B1);
+            Ok(())
+        },
+        run: run_tango,
+        post: || {
+            let rs_path = &Target::Src.path_buf("foo.rs");
+            let md_path = &Target::Lit.path_buf("foo.md");
+            let rs_t = (rs_path.metadata())?.timestamp();
+            assert!(TIME_C2 == rs_t, "rs_t: {:?} TIME_C2: {:?}", rs_t, TIME_C2);
+            let mut s = String::new();
+            (File::open(md_path)?.read_to_string(&mut s))?;
+            assert!(s == HELLO_WORLD_MD);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+const MERGE_BASE_MD: &str = "# Title
+
+```rust
+fn one() { }
+
+fn two() { }
+```
+";
+
+const MERGE_OURS_MD: &str = "# Edited Title
+
+```rust
+fn one() { }
+
+fn two() { }
+```
+";
+
+const MERGE_THEIRS_RS: &str = "//@ # Title
+
+fn one() { }
+
+fn two() { let edited = true; }
+";
+
+const MERGE_RESULT_MD: &str = "# Edited Title
+
+```rust
+fn one() { }
+
+fn two() { let edited = true; }
+```
+";
+
+fn write_file(t: Target, filename: &str, content: &str, timestamp: Timestamp) -> Result<(), TangoRunError> {
+    let mut f = (File::create(t.path_buf(filename)))?;
+    (write!(f, "{}", content))?;
+    drop(f);
+    touch_file(t, filename, timestamp)
+}
+
+fn read_file(t: Target, filename: &str) -> Result<String, TangoRunError> {
+    let mut s = String::new();
+    (File::open(t.path_buf(filename))?.read_to_string(&mut s))?;
+    Ok(s)
+}
+
+#[test]
+fn locked_then_update_both_merges() {
+    framework(Test {
+        name: "locked_then_update_both_merges",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", MERGE_BASE_MD, TIME_B1))?;
+            (run_tango())?;
+            (write_file(Target::Lit, "foo.md", MERGE_OURS_MD, TIME_C1))?;
+            write_file(Target::Src, "foo.rs", MERGE_THEIRS_RS, TIME_C2)
+        },
+        pre: || Ok(()),
+        run: run_tango,
+        post: || {
+            assert_eq!((read_file(Target::Lit, "foo.md"))?, MERGE_RESULT_MD);
+            let rs = (read_file(Target::Src, "foo.rs"))?;
+            assert!(rs.starts_with("//@ # Edited Title\n"), "rs: {}", rs);
+            assert!(rs.contains("fn two() { let edited = true; }"), "rs: {}", rs);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn locked_then_conflicting_updates() {
+    framework(Test {
+        name: "locked_then_conflicting_updates",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", MERGE_BASE_MD, TIME_B1))?;
+            (run_tango())?;
+            (write_file(Target::Lit, "foo.md", MERGE_OURS_MD, TIME_C1))?;
+            write_file(Target::Src, "foo.rs", "//@ # Other Title\n", TIME_C2)
+        },
+        pre: || Ok(()),
+        run: || {
+            assert!(run_tango().is_err(), "tango should fail on conflicts");
+            Ok(())
+        },
+        post: || {
+            let rs = (read_file(Target::Src, "foo.rs"))?;
+            let md = (read_file(Target::Lit, "foo.md"))?;
+            for content in &[&rs, &md] {
+                assert!(content.contains("<<<<<<< src/foo.md"), "content: {}", content);
+                assert!(content.contains(">>>>>>> src/foo.rs"), "content: {}", content);
+            }
+            // The pair is not recorded as in sync until the markers
+            // are resolved.
+            let lock = tango::lock::Lock::read(Target::Root.path_buf(tango::LOCK))?;
+            assert!(lock.entries().is_empty(), "{:?}", lock.entries());
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn locked_then_update_both_without_base() {
+    framework(Test {
+        name: "locked_then_update_both_without_base",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", HELLO_WORLD_MD, TIME_B1))?;
+            (run_tango())?;
+            (fs::remove_dir_all(Target::Root.path_buf(tango::BASE_DIR)))?;
+            (write_file(Target::Src, "foo.rs", HELLO_WORLD2_RS, TIME_C2))?;
+            write_file(Target::Lit, "foo.md", HELLO_WORLD2_MD, TIME_C1)
+        },
+        pre: || Ok(()),
+        run: || {
+            assert!(run_tango().is_err(), "tango should refuse diverged inputs");
+            Ok(())
+        },
+        post: || {
+            // Both sides diverged from `tango.lock` and there is no
+            // base to merge against, so neither may be overwritten.
+            let rs_t = (Target::Src.path_buf("foo.rs").metadata())?.timestamp();
+            let md_t = (Target::Lit.path_buf("foo.md").metadata())?.timestamp();
+            assert!(TIME_C2 == rs_t, "rs_t: {:?} TIME_C2: {:?}", rs_t, TIME_C2);
+            assert!(TIME_C1 == md_t, "md_t: {:?} TIME_C1: {:?}", md_t, TIME_C1);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn cli_md2rs_to_file() {
+    framework(Test {
+        name: "cli_md2rs_to_file",
+        setup: || create_file(Target::Lit, "foo.md", HELLO_WORLD_MD, TIME_B1).map_err(From::from),
+        pre: || Ok(()),
+        run: || run_tango_with(&["md2rs", "src/foo.md", "out.rs"]),
+        post: || {
+            assert_eq!((read_file(Target::Root, "out.rs"))?, HELLO_WORLD_RS);
+            // Converting a single file does not sync the tree.
+            assert!(!Target::Src.path_buf("foo.rs").exists());
+            assert!(!Target::Root.path_buf(tango::STAMP).exists());
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn cli_clean_removes_state() {
+    framework(Test {
+        name: "cli_clean_removes_state",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", HELLO_WORLD_MD, TIME_B1))?;
+            run_tango_with(&["sync"])
+        },
+        pre: || {
+            assert!(Target::Root.path_buf(tango::STAMP).exists());
+            assert!(Target::Root.path_buf(tango::LOCK).exists());
+            assert!(Target::Root.path_buf(tango::BASE_DIR).exists());
+            Ok(())
+        },
+        run: || run_tango_with(&["clean"]),
+        post: || {
+            assert!(!Target::Root.path_buf(tango::STAMP).exists());
+            assert!(!Target::Root.path_buf(tango::LOCK).exists());
+            assert!(!Target::Root.path_buf(tango::BASE_DIR).exists());
+            assert!(Target::Src.path_buf("foo.rs").exists());
+            assert!(Target::Lit.path_buf("foo.md").exists());
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn cli_exit_codes() {
+    framework(Test {
+        name: "cli_exit_codes",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", HELLO_WORLD_MD, TIME_B1))?;
+            (run_tango())?;
+            (write_file(Target::Lit, "foo.md", HELLO_WORLD2_MD, TIME_C1))?;
+            write_file(Target::Src, "foo.rs", "fn main() { }\n", TIME_C2)
+        },
+        pre: || {
+            (fs::remove_dir_all(Target::Root.path_buf(tango::BASE_DIR)))?;
+            Ok(())
+        },
+        run: || {
+            assert_eq!((tango_output(&["status"]))?.status.code(), Some(0));
+            assert_eq!((tango_output(&["check"]))?.status.code(), Some(1));
+            assert_eq!((tango_output(&["no-such-command"]))?.status.code(), Some(2));
+            Ok(())
+        },
+        post: || {
+            let status = (tango_output(&["status"]))?;
+            let stdout = String::from_utf8_lossy(&status.stdout);
+            assert!(stdout.contains("both changed  src/foo.rs <> src/foo.md"), "stdout: {}", stdout);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn plan_reports_without_writing() {
+    framework(Test {
+        name: "plan_reports_without_writing",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", HELLO_WORLD_MD, TIME_B1))?;
+            (run_tango())?;
+            (write_file(Target::Lit, "foo.md", HELLO_WORLD2_MD, TIME_B2))?;
+            create_file(Target::Lit, "bar.md", HELLO_WORLD_MD, TIME_B1).map_err(From::from)
+        },
+        pre: || Ok(()),
+        run: || {
+            let output = (tango_output(&["sync", "--dry-run"]))?;
+            assert_eq!(output.status.code(), Some(0));
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(stdout.contains("generate src/foo.md -> src/foo.rs: source changed since last sync"),
+                    "stdout: {}", stdout);
+            assert!(stdout.contains("skip     src/foo.rs -> src/foo.md: target changed since last sync"),
+                    "stdout: {}", stdout);
+            assert!(stdout.contains("generate src/bar.md -> src/bar.rs: target does not exist"),
+                    "stdout: {}", stdout);
+            Ok(())
+        },
+        post: || {
+            assert!(!Target::Src.path_buf("bar.rs").exists());
+            assert_eq!((read_file(Target::Src, "foo.rs"))?, HELLO_WORLD_RS);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn check_passes_when_in_sync() {
+    framework(Test {
+        name: "check_passes_when_in_sync",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", HELLO_WORLD_MD, TIME_B1))?;
+            run_tango()
+        },
+        // Scrambling the timestamps must not affect the check.
+        pre: || {
+            (touch_file(Target::Lit, "foo.md", TIME_C3))?;
+            touch_file(Target::Src, "foo.rs", TIME_A1)
+        },
+        run: || {
+            let output = (tango_output(&["check"]))?;
+            assert_eq!(output.status.code(), Some(0), "{:?}", output);
+            Ok(())
+        },
+        post: || Ok(()),
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn check_reports_out_of_sync_with_diff() {
+    framework(Test {
+        name: "check_reports_out_of_sync_with_diff",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", HELLO_WORLD_MD, TIME_B1))?;
+            (run_tango())?;
+            // Edit the .md but leave it older than the .rs, so an
+            // mtime-based check would consider the pair up to date.
+            write_file(Target::Lit, "foo.md", HELLO_WORLD2_MD, TIME_A1)
+        },
+        pre: || Ok(()),
+        run: || {
+            let output = (tango_output(&["check"]))?;
+            assert_eq!(output.status.code(), Some(1));
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(stdout.contains("--- src/foo.rs\n+++ src/foo.rs (generated from src/foo.md)\n"),
+                    "stdout: {}", stdout);
+            assert!(stdout.contains("\n-fn main() { println!(\"Hello World\"); }\n"), "stdout: {}", stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains("src/foo.rs is out of sync with src/foo.md"), "stderr: {}", stderr);
+            Ok(())
+        },
+        post: || {
+            assert_eq!((read_file(Target::Src, "foo.rs"))?, HELLO_WORLD_RS);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn watch_resyncs_changed_pairs() {
+    framework(Test {
+        name: "watch_resyncs_changed_pairs",
+        setup: || {
+            (create_file(Target::Lit, "foo.md", HELLO_WORLD_MD, TIME_B1))?;
+            (create_file(Target::Lit, "bar.md", HELLO_WORLD_MD, TIME_B1))?;
+            run_tango()
+        },
+        pre: || Ok(()),
+        run: || {
+            let bar_time = (fs::metadata(Target::Src.path_buf("bar.rs")))?.modified()?;
+            let mut child = (spawn_tango(&["watch", "--debounce", "50"]))?;
+            // Give the watcher time to take its initial snapshot.
+            thread::sleep(Duration::from_millis(500));
+            (write_file(Target::Lit, "foo.md", HELLO_WORLD2_MD, TIME_C1))?;
+
+            // The sync is done once `tango.lock` records it, after
+            // foo.rs is written.
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let mut synced = false;
+            while !synced && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+                synced = (read_file(Target::Src, "foo.rs"))? == HELLO_WORLD2_RS
+                    && String::from_utf8_lossy(&(tango_output(&["status"]))?.stdout)
+                        .contains("in sync       src/foo.rs == src/foo.md");
+            }
+            (child.kill())?;
+            (child.wait())?;
+            assert!(synced, "watch did not sync foo.rs");
+            assert_eq!((fs::metadata(Target::Src.path_buf("bar.rs")))?.modified()?, bar_time);
+            Ok(())
+        },
+        post: || {
+            let output = (tango_output(&["check"]))?;
+            assert_eq!(output.status.code(), Some(0), "{:?}", output);
+            let status = (tango_output(&["status"]))?;
+            let stdout = String::from_utf8_lossy(&status.stdout);
+            assert!(stdout.contains("in sync       src/foo.rs == src/foo.md"), "stdout: {}", stdout);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn watch_ignores_md_files_among_the_sources() {
+    framework(Test {
+        name: "watch_ignores_md_files_among_the_sources",
+        setup: || {
+            (fs::create_dir_all(Target::Root.path_buf("lit")))?;
+            (create_file(Target::Root, "lit/foo.md", HELLO_WORLD_MD, TIME_B1))?;
+            (create_file(Target::Src, "NOTES.md", "# Notes\n", TIME_B1))?;
+            run_tango_with(&["--lit-dir", "lit"])
+        },
+        pre: || Ok(()),
+        run: || {
+            let mut child = (spawn_tango(&["--lit-dir", "lit", "watch", "--debounce", "50"]))?;
+            thread::sleep(Duration::from_millis(500));
+            (write_file(Target::Src, "NOTES.md", "# Notes\n\nMore.\n", TIME_C1))?;
+            thread::sleep(Duration::from_millis(500));
+            (write_file(Target::Root, "lit/foo.md", HELLO_WORLD2_MD, TIME_C1))?;
+
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let mut synced = false;
+            while !synced && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+                synced = (read_file(Target::Src, "foo.rs"))? == HELLO_WORLD2_RS;
+            }
+            let exited = (child.try_wait())?;
+            (child.kill())?;
+            (child.wait())?;
+            assert_eq!(exited, None, "watch exited");
+            assert!(synced, "watch did not regenerate foo.rs");
+            Ok(())
+        },
+        post: || {
+            assert!(!Target::Src.path_buf("NOTES.rs").exists());
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn concurrent_syncs_with_explicit_roots() {
+    framework(Test {
+        name: "concurrent_syncs_with_explicit_roots",
+        setup: || {
+            for &(project, content) in &[("a", HELLO_WORLD_MD), ("b", HELLO_WORLD2_MD)] {
+                (fs::create_dir_all(Target::Root.path_buf(project).join("src")))?;
+                let mut f = (File::create(Target::Root.path_buf(project).join("src/foo.md")))?;
+                (f.write_all(content.as_bytes()))?;
+            }
+            Ok(())
+        },
+        pre: || Ok(()),
+        run: || {
+            let threads: Vec<_> = ["a", "b"].iter().map(|project| {
+                let mut config = tango::Config::new();
+                config.set_root(Target::Root.path_buf(project));
+                thread::spawn(move || tango::process_root_with_config(config))
+            }).collect();
+            for t in threads {
+                t.join().expect("sync thread panicked").unwrap_or_panic("sync error");
+            }
+            Ok(())
+        },
+        post: || {
+            for &(project, content) in &[("a", HELLO_WORLD_RS), ("b", HELLO_WORLD2_RS)] {
+                let root = Target::Root.path_buf(project);
+                let mut s = String::new();
+                (File::open(root.join("src/foo.rs"))?.read_to_string(&mut s))?;
+                assert_eq!(s, content);
+                assert!(root.join(tango::STAMP).exists());
+                // The lock records paths relative to the root.
+                let lock = tango::lock::Lock::read(root.join(tango::LOCK))?;
+                assert_eq!(lock.entries()[0].rs_path, PathBuf::from("src/foo.rs"));
+            }
+            assert!(!Target::Root.path_buf(tango::STAMP).exists());
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn separate_multi_component_roots() {
+    framework(Test {
+        name: "separate_multi_component_roots",
+        setup: || {
+            (fs::create_dir_all(Target::Root.path_buf("crates/foo/src/net")))?;
+            (fs::create_dir_all(Target::Root.path_buf("docs/lit/foo")))?;
+            let mut f = (File::create(Target::Root.path_buf("crates/foo/src/net/tcp.rs")))?;
+            (f.write_all(HELLO_WORLD_RS.as_bytes()))?;
+            let mut f = (File::create(Target::Root.path_buf("docs/lit/foo/lib.md")))?;
+            (f.write_all(HELLO_WORLD2_MD.as_bytes()))?;
+            Ok(())
+        },
+        pre: || Ok(()),
+        run: || {
+            run_tango_with(&["--src-dir", "crates/foo/src", "--lit-dir", "docs/lit/foo"])
+        },
+        post: || {
+            let mut s = String::new();
+            (File::open(Target::Root.path_buf("docs/lit/foo/net/tcp.md"))?.read_to_string(&mut s))?;
+            assert_eq!(s, HELLO_WORLD_MD);
+            let mut s = String::new();
+            (File::open(Target::Root.path_buf("crates/foo/src/lib.rs"))?.read_to_string(&mut s))?;
+            assert_eq!(s, HELLO_WORLD2_RS);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn config_file_excludes_and_maps_files() {
+    framework(Test {
+        name: "config_file_excludes_and_maps_files",
+        setup: || {
+            (fs::create_dir_all(Target::Src.path_buf("generated")))?;
+            (create_file(Target::Src, "generated/out.rs", HELLO_WORLD_RS, TIME_A1))?;
+            (create_file(Target::Src, "lib.rs", HELLO_WORLD2_RS, TIME_A1))?;
+            create_file(Target::Root, "tango.toml", "\
+[files]
+exclude = [\"src/generated/**\"]
+
+[files.map]
+\"src/lib.rs\" = \"README.md\"
+", TIME_A1).map_err(From::from)
+        },
+        pre: || Ok(()),
+        run: || run_tango(),
+        post: || {
+            assert!(!Target::Src.path_buf("generated/out.md").exists());
+            assert!(!Target::Src.path_buf("lib.md").exists());
+            assert_eq!((read_file(Target::Root, "README.md"))?, HELLO_WORLD2_MD);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn cargo_metadata_and_options_layer_over_config_file() {
+    framework(Test {
+        name: "cargo_metadata_and_options_layer_over_config_file",
+        setup: || {
+            (create_file(Target::Src, "foo.rs", HELLO_WORLD_RS, TIME_A1))?;
+            (create_file(Target::Root, "Cargo.toml", "\
+[package]
+name = \"demo\"
+version = \"0.1.0\"
+
+[package.metadata.tango]
+lit-dir = \"doc\"
+fence-languages = [\"rs\"]
+", TIME_A1))?;
+            create_file(Target::Root, "tango.toml", "lit-dir = \"book\"\n", TIME_A1)
+                .map_err(From::from)
+        },
+        pre: || Ok(()),
+        run: || run_tango_with(&["--lit-dir", "manual"]),
+        post: || {
+            assert!(!Target::Root.path_buf("doc/foo.md").exists());
+            assert!(!Target::Root.path_buf("book/foo.md").exists());
+            assert_eq!((read_file(Target::Root, "manual/foo.md"))?,
+                       HELLO_WORLD_MD.replace("```rust", "```rs"));
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+const SPLIT_DESIGN_MD: &str = "# Design
+
+```rust
+mod lexer;
+```
+
+```rust,file=src/lexer.rs
+pub fn lex() {}
+```
+";
+
+#[test]
+fn file_blocks_sync_as_a_group() {
+    framework(Test {
+        name: "file_blocks_sync_as_a_group",
+        setup: || {
+            (create_file(Target::Lit, "design.md", SPLIT_DESIGN_MD, TIME_B1))?;
+            run_tango()
+        },
+        pre: || {
+            assert_eq!((read_file(Target::Src, "lexer.rs"))?,
+                       "//@from src/design.md\npub fn lex() {}\n");
+            assert!((read_file(Target::Src, "design.rs"))?.contains("//@@,file=src/lexer.rs\n"));
+            Ok(())
+        },
+        run: || {
+            // Editing just the piece brings the whole group up to date.
+            (write_file(Target::Src, "lexer.rs",
+                        "//@from src/design.md\npub fn lex() -> usize { 0 }\n", TIME_C1))?;
+            run_tango()
+        },
+        post: || {
+            assert!(!Target::Lit.path_buf("lexer.md").exists());
+            assert_eq!((read_file(Target::Lit, "design.md"))?,
+                       SPLIT_DESIGN_MD.replace("pub fn lex() {}", "pub fn lex() -> usize { 0 }"));
+            let lock = tango::lock::Lock::read(Target::Root.path_buf(tango::LOCK))?;
+            assert_eq!(lock.entries()[0].pieces, vec![PathBuf::from("src/lexer.rs")]);
+            let output = (tango_output(&["check"]))?;
+            assert_eq!(output.status.code(), Some(0), "{:?}", output);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn locked_then_update_both_with_pieces_merges() {
+    framework(Test {
+        name: "locked_then_update_both_with_pieces_merges",
+        setup: || {
+            (create_file(Target::Lit, "design.md", SPLIT_DESIGN_MD, TIME_B1))?;
+            (run_tango())?;
+            (write_file(Target::Lit, "design.md",
+                        &SPLIT_DESIGN_MD.replace("pub fn lex() {}", "pub fn lex() -> usize { 0 }"), TIME_C1))?;
+            let rs = (read_file(Target::Src, "design.rs"))?;
+            write_file(Target::Src, "design.rs", &rs.replace("mod lexer;", "pub mod lexer;"), TIME_C2)
+        },
+        pre: || Ok(()),
+        run: run_tango,
+        post: || {
+            assert_eq!((read_file(Target::Lit, "design.md"))?,
+                       SPLIT_DESIGN_MD.replace("pub fn lex() {}", "pub fn lex() -> usize { 0 }")
+                                      .replace("mod lexer;", "pub mod lexer;"));
+            assert_eq!((read_file(Target::Src, "lexer.rs"))?,
+                       "//@from src/design.md\npub fn lex() -> usize { 0 }\n");
+            let output = (tango_output(&["check"]))?;
+            assert_eq!(output.status.code(), Some(0), "{:?}", output);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+// Outside the root of the tests' project.
+const VICTIM: &str = "../file_blocks_outside_src_are_refused.rs";
+
+#[test]
+fn file_blocks_outside_src_are_refused() {
+    framework(Test {
+        name: "file_blocks_outside_src_are_refused",
+        setup: || {
+            // (Left over, perhaps, from a run that failed.)
+            let _ = fs::remove_file(Target::Root.path_buf(VICTIM));
+            (create_file(Target::Root, VICTIM, "keep\n", TIME_A1))?;
+            // A `.rs` file naming it, say from before it was refused.
+            (create_file(Target::Src, "design.rs",
+                         "//@ # Design\n\n//@@,file=../file_blocks_outside_src_are_refused.rs\nfn f() {}\n", TIME_A1))?;
+            run_tango()
+        },
+        pre: || {
+            assert_eq!((read_file(Target::Root, VICTIM))?, "keep\n");
+            Ok(())
+        },
+        run: || {
+            (write_file(Target::Lit, "design.md",
+                        &SPLIT_DESIGN_MD.replace("file=src/lexer.rs", "file=../file_blocks_outside_src_are_refused.rs"),
+                        TIME_B1))?;
+            let output = (tango_output(&[]))?;
+            assert_eq!(output.status.code(), Some(1), "{:?}", output);
+            assert!(String::from_utf8_lossy(&output.stderr).contains("must name a `.rs` file under `src/`"),
+                    "{:?}", output);
+            // Once the block is gone, the file is not removed either.
+            (write_file(Target::Lit, "design.md", "# Design\n", TIME_C1))?;
+            run_tango()
+        },
+        post: || {
+            assert_eq!((read_file(Target::Root, VICTIM))?, "keep\n");
+            assert_eq!((read_file(Target::Src, "design.rs"))?, "//@ # Design\n");
+            fs::remove_file(Target::Root.path_buf(VICTIM)).map_err(From::from)
+        }
+    }).unwrap_or_panic("test error")
+}
+
+const CHAPTER_INTRO_MD: &str = "# Introduction
+
+```rust
+mod types;
+```
+";
+
+const CHAPTER_TYPES_MD: &str = "# Types
+
+```rust
+pub struct Token;
+```
+";
+
+#[test]
+fn chapters_sync_as_a_group() {
+    framework(Test {
+        name: "chapters_sync_as_a_group",
+        setup: || {
+            (create_file(Target::Lit, "01-intro.md", CHAPTER_INTRO_MD, TIME_B1))?;
+            (create_file(Target::Lit, "02-types.md", CHAPTER_TYPES_MD, TIME_B2))?;
+            (create_file(Target::Root, "tango.toml", "\
+[files.chapters]
+\"src/lib.rs\" = [\"src/01-intro.md\", \"src/02-types.md\"]
+", TIME_A1))?;
+            run_tango()
+        },
+        pre: || {
+            assert!(!Target::Src.path_buf("01-intro.rs").exists());
+            assert!(!Target::Src.path_buf("02-types.rs").exists());
+            let rs = (read_file(Target::Src, "lib.rs"))?;
+            assert!(rs.starts_with("//@chapter src/01-intro.md\n"), "rs: {}", rs);
+            assert!(rs.contains("\n//@chapter src/02-types.md\n//@ # Types\n"), "rs: {}", rs);
+            Ok(())
+        },
+        run: || {
+            let rs = (read_file(Target::Src, "lib.rs"))?;
+            (write_file(Target::Src, "lib.rs", &rs.replace("pub struct Token;", "pub struct Token(u8);"), TIME_C1))?;
+            run_tango()
+        },
+        post: || {
+            assert_eq!((read_file(Target::Lit, "01-intro.md"))?, CHAPTER_INTRO_MD);
+            assert_eq!((read_file(Target::Lit, "02-types.md"))?,
+                       CHAPTER_TYPES_MD.replace("pub struct Token;", "pub struct Token(u8);"));
+            let output = (tango_output(&["check"]))?;
+            assert_eq!(output.status.code(), Some(0), "{:?}", output);
+            let status = (tango_output(&["status"]))?;
+            let stdout = String::from_utf8_lossy(&status.stdout);
+            assert!(stdout.contains("in sync       src/lib.rs == src/01-intro.md"), "stdout: {}", stdout);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn map_translates_locations() {
+    framework(Test {
+        name: "map_translates_locations",
+        setup: || {
+            (create_file(Target::Lit, "01-intro.md", CHAPTER_INTRO_MD, TIME_B1))?;
+            (create_file(Target::Lit, "02-types.md", CHAPTER_TYPES_MD, TIME_B2))?;
+            (create_file(Target::Root, "tango.toml", "\
+[files.chapters]
+\"src/lib.rs\" = [\"src/01-intro.md\", \"src/02-types.md\"]
+", TIME_A1))?;
+            run_tango()
+        },
+        pre: || {
+            assert!(Target::Root.path_buf("tango.map/src/lib.rs.map").exists());
+            let output = (tango_output(&["map", "src/lib.rs:8:5"]))?;
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "src/02-types.md:4:5\n");
+            let output = (tango_output(&["map", "src/02-types.md:4:5"]))?;
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "src/lib.rs:8:5\n");
+            let output = (tango_output(&["map", "src/lib.rs:2"]))?;
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "src/01-intro.md:1:1\n");
+            Ok(())
+        },
+        run: || {
+            (write_file(Target::Lit, "02-types.md",
+                        &CHAPTER_TYPES_MD.replace("# Types\n", "# Types\n\nTokens first.\n"), TIME_C1))?;
+            run_tango()
+        },
+        post: || {
+            // The map is brought up to date along with the `.rs` file.
+            let output = (tango_output(&["map", "src/lib.rs:10:1"]))?;
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "src/02-types.md:6:1\n");
+            let output = (tango_output(&["map", "src/lib.rs:99"]))?;
+            assert_eq!(output.status.code(), Some(1), "{:?}", output);
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn conversion_errors_point_at_md_files() {
+    framework(Test {
+        name: "conversion_errors_point_at_md_files",
+        setup: || {
+            // (References are only checked where some chunk is defined.)
+            (create_file(Target::Lit, "01-intro.md",
+                         &CHAPTER_INTRO_MD.replace("```rust\n", "```rust <<intro>>=\n"), TIME_B1))?;
+            (create_file(Target::Lit, "02-types.md",
+                         &CHAPTER_TYPES_MD.replace("pub struct Token;\n", "pub struct Token;\n    <<tokens>>\n"),
+                         TIME_B2))?;
+            create_file(Target::Root, "tango.toml", "\
+[files.chapters]
+\"src/lib.rs\" = [\"src/01-intro.md\", \"src/02-types.md\"]
+", TIME_A1).map_err(From::from)
+        },
+        pre: || Ok(()),
+        run: || {
+            let output = (tango_output(&[]))?;
+            assert_eq!(output.status.code(), Some(1), "{:?}", output);
+            assert_eq!(String::from_utf8_lossy(&output.stderr), "\
+error[undefined-chunk]: reference to undefined chunk `<<tokens>>`
+ --> src/02-types.md:5:5
+  |
+5 |     <<tokens>>
+  |     ^^^^^^^^^^
+
+");
+            Ok(())
+        },
+        post: || {
+            assert!(!Target::Src.path_buf("lib.rs").exists());
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}
+
+#[test]
+fn cargo_diagnostics_point_at_md_files() {
+    framework(Test {
+        name: "cargo_diagnostics_point_at_md_files",
+        setup: || {
+            (create_file(Target::Lit, "01-intro.md", CHAPTER_INTRO_MD, TIME_B1))?;
+            (create_file(Target::Lit, "02-types.md", CHAPTER_TYPES_MD, TIME_B2))?;
+            (create_file(Target::Root, "tango.toml", "\
+[files.chapters]
+\"src/lib.rs\" = [\"src/01-intro.md\", \"src/02-types.md\"]
+", TIME_A1))?;
+            run_tango()
+        },
+        pre: || Ok(()),
+        run: || Ok(()),
+        post: || {
+            let message = r#"{"reason":"compiler-message","message":{"message":"unused","level":"warning","spans":[{"file_name":"src/lib.rs","line_start":8,"line_end":8,"column_start":12,"column_end":17}],"children":[]}}"#;
+            let finished = r#"{"reason":"build-finished","success":true}"#;
+            let input = format!("{}\n{}\n", message, finished);
+            let output = (tango_output_with_input(&["cargo-diagnostics"], &input))?;
+            assert_eq!(output.status.code(), Some(0), "{:?}", output);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let expect = message.replace("src/lib.rs", "src/02-types.md")
+                .replace(r#""line_start":8,"line_end":8"#, r#""line_start":4,"line_end":4"#);
+            assert_eq!(stdout, format!("{}\n{}\n", expect, finished));
+            Ok(())
+        }
+    }).unwrap_or_panic("test error")
+}